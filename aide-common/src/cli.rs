@@ -1,5 +1,16 @@
 use clap::Parser;
+use reqwest::header::{HeaderMap, HeaderValue, InvalidHeaderValue, AUTHORIZATION};
 use std::net::IpAddr;
+use thiserror::Error;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Invalid token [{0}]")]
+    Token(#[from] InvalidHeaderValue),
+    #[error("Error building the HTTP client [{0}]")]
+    Build(#[from] reqwest::Error),
+}
 
 #[derive(Parser, Clone, Debug)]
 pub struct CommonOpt {
@@ -7,6 +18,22 @@ pub struct CommonOpt {
     /// Register to aide as plugin
     pub registration: bool,
 
+    #[clap(
+        long = "hub",
+        env = "AIDE_HUB_URL",
+        default_value = "http://127.0.0.1:9090/"
+    )]
+    /// The URL of the aide hub, used with --registration
+    pub hub_url: Url,
+
+    #[clap(long)]
+    /// The name used to register to aide [default: the plugin name]
+    pub name: Option<String>,
+
+    #[clap(long, env = "AIDE_TOKEN", hide_env_values = true)]
+    /// The API token clients have to send as bearer token; also used to register to aide
+    pub token: Option<String>,
+
     #[clap(name = "host", long = "host", short = 'H', default_value = "127.0.0.1")]
     /// Set the listening IP address
     pub host_addr: IpAddr,
@@ -14,6 +41,11 @@ pub struct CommonOpt {
     #[clap(short = 'p', long, default_value_t = 80)]
     /// Listening TCP port of the server
     pub port: u16,
+
+    #[clap(long)]
+    /// The IP address the hub uses to reach the plugin, used with --registration
+    /// [default: the listening address]
+    pub advertise_addr: Option<IpAddr>,
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -35,6 +67,10 @@ pub struct CliCommonOpt {
     #[clap(long = "no-tls", global = true)]
     /// Force http to connect to the server [default: false]
     pub notls: bool,
+
+    #[clap(long, env = "AIDE_TOKEN", hide_env_values = true, global = true)]
+    /// The API token to authenticate to the server
+    pub token: Option<String>,
 }
 
 impl CliCommonOpt {
@@ -45,4 +81,20 @@ impl CliCommonOpt {
             "https"
         }
     }
+
+    /// The value of the Authorization header, if a token is set
+    pub fn get_auth_header(&self) -> Option<String> {
+        self.token.as_ref().map(|t| format!("Bearer {}", t))
+    }
+
+    /// An HTTP client sending the token, if set, with every request
+    pub fn get_client(&self) -> Result<reqwest::blocking::Client, ClientError> {
+        let mut headers = HeaderMap::new();
+        if let Some(auth) = self.get_auth_header() {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth)?);
+        }
+        Ok(reqwest::blocking::Client::builder()
+            .default_headers(headers)
+            .build()?)
+    }
 }
@@ -1,6 +1,22 @@
-use hyper::{Body, Response, StatusCode};
+use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{Body, Request, Response, StatusCode};
 use std::string::ToString;
 
+pub fn http_400<T: ToString>(s: &T) -> Response<Body> {
+    hyper::Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(s.to_string()))
+        .unwrap()
+}
+
+pub fn http_401<T: ToString>(s: &T) -> Response<Body> {
+    hyper::Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(WWW_AUTHENTICATE, "Bearer")
+        .body(Body::from(s.to_string()))
+        .unwrap()
+}
+
 pub fn http_404<T: ToString>(s: &T) -> Response<Body> {
     hyper::Response::builder()
         .status(StatusCode::NOT_FOUND)
@@ -8,6 +24,76 @@ pub fn http_404<T: ToString>(s: &T) -> Response<Body> {
         .unwrap()
 }
 
+pub fn http_502<T: ToString>(s: &T) -> Response<Body> {
+    hyper::Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Body::from(s.to_string()))
+        .unwrap()
+}
+
+pub fn http_503<T: ToString>(s: &T) -> Response<Body> {
+    hyper::Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .body(Body::from(s.to_string()))
+        .unwrap()
+}
+
 pub fn healthz() -> Response<Body> {
     hyper::Response::new(Body::from("OK"))
 }
+
+/// Check the bearer token of the request, returning the 401 response to send back if the
+/// token is missing or wrong
+///
+/// If the server has no token configured, every request is accepted
+pub fn check_token(req: &Request<Body>, token: Option<&str>) -> Option<Response<Body>> {
+    let token = token?;
+    let provided = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match provided {
+        Some(p) if constant_time_eq(p.as_bytes(), token.as_bytes()) => None,
+        Some(_) => Some(http_401(&"Invalid token")),
+        None => Some(http_401(&"Missing token")),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<Body> {
+        let mut builder = Request::get("/v1/todos");
+        if let Some(a) = authorization {
+            builder = builder.header(AUTHORIZATION, a);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn no_token_configured() {
+        assert!(check_token(&request(None), None).is_none());
+        assert!(check_token(&request(Some("Bearer whatever")), None).is_none());
+    }
+
+    #[test]
+    fn token_configured() {
+        let token = Some("s3cr3t");
+        assert!(check_token(&request(Some("Bearer s3cr3t")), token).is_none());
+        let got = check_token(&request(None), token).unwrap();
+        assert_eq!(got.status(), StatusCode::UNAUTHORIZED);
+        let got = check_token(&request(Some("Bearer s3cr3")), token).unwrap();
+        assert_eq!(got.status(), StatusCode::UNAUTHORIZED);
+        let got = check_token(&request(Some("s3cr3t")), token).unwrap();
+        assert_eq!(got.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -3,7 +3,18 @@ pub use cli::CliCommonOpt;
 pub use cli::CommonOpt;
 
 pub mod http;
+pub use http::check_token;
 pub use http::healthz;
+pub use http::http_400;
+pub use http::http_401;
 pub use http::http_404;
+pub use http::http_502;
+pub use http::http_503;
+
+pub mod proxy;
+
+pub mod registration;
+pub use registration::shutdown_signal;
+pub use registration::Registration;
 
 pub mod tui;
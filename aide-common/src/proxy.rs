@@ -0,0 +1,155 @@
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Body, Client, Request, Response, Uri};
+use std::net::IpAddr;
+use thiserror::Error;
+use url::Url;
+
+pub type HttpClient = Client<HttpConnector>;
+
+/// The headers about a single connection, never forwarded
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("Invalid upstream URI [{0:?}]")]
+    Uri(#[from] hyper::http::Error),
+    #[error("Upstream not reachable [{0:?}]")]
+    Hyper(#[from] hyper::Error),
+}
+
+/// Forward the request, as it is, to the upstream server reachable at `base`
+///
+/// Method, headers, path, query and body are preserved, except the hop-by-hop headers; the
+/// response of the upstream server is returned unchanged
+pub async fn forward(
+    client: &HttpClient,
+    base: &Url,
+    req: Request<Body>,
+) -> Result<Response<Body>, ProxyError> {
+    let (mut parts, body) = req.into_parts();
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    parts.uri = upstream_uri(base, path_and_query)?;
+    parts.headers.remove(hyper::header::HOST);
+    strip_hop_by_hop(&mut parts.headers);
+    // upstream servers speak HTTP/1.1 only, whatever the client negotiated
+    parts.version = hyper::Version::HTTP_11;
+    let upstream_req = Request::from_parts(parts, body);
+    Ok(client.request(upstream_req).await?)
+}
+
+/// Remove the hop-by-hop headers, including the ones listed in `Connection`
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+}
+
+/// Add the address of `peer` to the `X-Forwarded-For` chain
+pub fn append_forwarded_for(headers: &mut HeaderMap, peer: IpAddr) {
+    let chain = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .chain(std::iter::once(peer.to_string().as_str()))
+        .collect::<Vec<&str>>()
+        .join(", ");
+    if let Ok(v) = HeaderValue::from_str(&chain) {
+        headers.insert("x-forwarded-for", v);
+    }
+}
+
+/// The URI of `path_and_query` on the upstream server at `base`, appended to the path of
+/// `base`
+///
+/// Scheme and authority always come from `base`: the request path is never resolved as a
+/// URL, so a path like `/http://other.host/` can't reach another server
+fn upstream_uri(base: &Url, path_and_query: &str) -> Result<Uri, ProxyError> {
+    let authority = &base[url::Position::BeforeHost..url::Position::AfterPort];
+    let path_and_query = format!(
+        "{}/{}",
+        base.path().trim_end_matches('/'),
+        path_and_query.trim_start_matches('/')
+    );
+    Ok(Uri::builder()
+        .scheme(base.scheme())
+        .authority(authority)
+        .path_and_query(path_and_query)
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstream_uri_keeps_the_base() {
+        let base = Url::parse("http://127.0.0.1:8080/").unwrap();
+        let uut = upstream_uri(&base, "/v1/todos?done=false").unwrap();
+        assert_eq!(uut, "http://127.0.0.1:8080/v1/todos?done=false");
+        assert_eq!(upstream_uri(&base, "/").unwrap(), "http://127.0.0.1:8080/");
+        let base = Url::parse("http://localhost:8080/api").unwrap();
+        let uut = upstream_uri(&base, "/v1/todos").unwrap();
+        assert_eq!(uut, "http://localhost:8080/api/v1/todos");
+    }
+
+    #[test]
+    fn upstream_uri_with_url_in_path() {
+        let base = Url::parse("http://127.0.0.1:8080/").unwrap();
+        let uut = upstream_uri(&base, "/http://169.254.169.254/latest/meta-data").unwrap();
+        assert_eq!(uut.host(), Some("127.0.0.1"));
+        assert_eq!(uut.port_u16(), Some(8080));
+        assert_eq!(uut.path(), "/http://169.254.169.254/latest/meta-data");
+        let uut = upstream_uri(&base, "//169.254.169.254/latest").unwrap();
+        assert_eq!(uut.host(), Some("127.0.0.1"));
+    }
+
+    #[test]
+    fn hop_by_hop_headers_stripped() {
+        let mut uut = HeaderMap::new();
+        uut.insert("connection", HeaderValue::from_static("keep-alive, x-hop"));
+        uut.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        uut.insert("upgrade", HeaderValue::from_static("websocket"));
+        uut.insert("x-hop", HeaderValue::from_static("1"));
+        uut.insert("authorization", HeaderValue::from_static("Bearer t"));
+        strip_hop_by_hop(&mut uut);
+        assert_eq!(uut.len(), 1);
+        assert_eq!(uut["authorization"], "Bearer t");
+    }
+
+    #[test]
+    fn forwarded_for_appended() {
+        let mut uut = HeaderMap::new();
+        append_forwarded_for(&mut uut, "10.0.0.1".parse().unwrap());
+        assert_eq!(uut["x-forwarded-for"], "10.0.0.1");
+        append_forwarded_for(&mut uut, "::1".parse().unwrap());
+        assert_eq!(uut["x-forwarded-for"], "10.0.0.1, ::1");
+        uut.append("x-forwarded-for", HeaderValue::from_static("10.0.0.2"));
+        append_forwarded_for(&mut uut, "10.0.0.3".parse().unwrap());
+        assert_eq!(uut["x-forwarded-for"], "10.0.0.1, ::1, 10.0.0.2, 10.0.0.3");
+        assert_eq!(uut.get_all("x-forwarded-for").iter().count(), 1);
+    }
+}
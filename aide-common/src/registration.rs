@@ -0,0 +1,319 @@
+use crate::CommonOpt;
+use aide_proto::v1::{
+    DeregisterModuleResponse, HeartbeatRequest, HeartbeatResponse, ModuleKind,
+    RegisterModuleRequest, RegisterModuleResponse, API_VERSION,
+};
+use hyper::header::{HeaderValue, AUTHORIZATION};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// The shortest heartbeat interval accepted from the hub, so plugins don't flood it
+const MIN_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// The longest wait for a reply of the hub, so that an unreachable hub doesn't block the
+/// shutdown
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum RegistrationError {
+    #[error("Invalid URL [{0:?}]")]
+    Url(#[from] url::ParseError),
+    #[error("Error building the request [{0:?}]")]
+    Request(#[from] hyper::http::Error),
+    #[error("Error connecting to the hub [{0:?}]")]
+    Hyper(#[from] hyper::Error),
+    #[error("Error (de)serializing the payload [{0:?}]")]
+    Json(#[from] serde_json::Error),
+    #[error("The hub didn't reply within {0:?}")]
+    Timeout(Duration),
+    #[error("The hub refused the request: {0} {1}")]
+    Refused(StatusCode, String),
+    #[error("Invalid token, it can't be sent as header [{0}]")]
+    Token(#[from] hyper::header::InvalidHeaderValue),
+    #[error("The plugin listens on {0}, which a remote hub can't reach: use --advertise-addr")]
+    UnreachableCallback(IpAddr),
+}
+
+/// The registration of a plugin to the aide hub
+#[derive(Debug, Clone)]
+pub struct Registration {
+    hub_url: Url,
+    /// The Authorization header, if a token is set
+    auth: Option<HeaderValue>,
+    request: RegisterModuleRequest,
+}
+
+impl Registration {
+    /// Build the registration of a plugin, using the listening address and the hub URL in `opt`
+    ///
+    /// `default_name` is used if no name has been specified on the command line.
+    /// If the plugin listens on an unspecified address and no address to advertise is
+    /// given, the loopback address is advertised, as long as the hub is local too
+    pub fn new(
+        opt: &CommonOpt,
+        default_name: &str,
+        kind: ModuleKind,
+    ) -> Result<Self, RegistrationError> {
+        let host_addr = match (opt.advertise_addr, opt.host_addr) {
+            (Some(a), _) => a,
+            (None, a) if a.is_unspecified() && !is_loopback(&opt.hub_url) => {
+                return Err(RegistrationError::UnreachableCallback(a));
+            }
+            (None, IpAddr::V4(a)) if a.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            (None, IpAddr::V6(a)) if a.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            (None, a) => a,
+        };
+        let callback = Url::parse(&format!(
+            "http://{}/",
+            std::net::SocketAddr::new(host_addr, opt.port)
+        ))?;
+        let request = RegisterModuleRequest {
+            name: opt.name.clone().unwrap_or_else(|| default_name.to_string()),
+            kind,
+            callback,
+            api_version: API_VERSION.to_string(),
+        };
+        let auth = match &opt.token {
+            Some(token) => Some(HeaderValue::from_str(&format!("Bearer {}", token))?),
+            None => None,
+        };
+        Ok(Registration {
+            hub_url: opt.hub_url.clone(),
+            auth,
+            request,
+        })
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.request.name
+    }
+
+    /// Register the plugin to the hub, one attempt only
+    ///
+    /// On success, it returns how often the hub expects an heartbeat
+    pub async fn register(&self) -> Result<Duration, RegistrationError> {
+        let url = self.hub_url.join("v1/modules")?;
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(url.as_str())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&self.request)?))?;
+        let response: RegisterModuleResponse = self.send(req).await?;
+        Ok(heartbeat_interval(&response))
+    }
+
+    /// Register the plugin to the hub, retrying with an exponential backoff until the hub
+    /// accepts it
+    pub async fn register_with_retry(&self) -> Duration {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match self.register().await {
+                Ok(heartbeat_interval) => {
+                    info!(
+                        "Registered to aide at {} as {}",
+                        self.hub_url,
+                        self.get_name()
+                    );
+                    return heartbeat_interval;
+                }
+                Err(e) => {
+                    warn!("Registration failed, retrying in {:?} [{}]", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Send an heartbeat to the hub, returning if the plugin is still registered
+    pub async fn heartbeat(&self) -> Result<bool, RegistrationError> {
+        let url = self.hub_url.join("v1/heartbeat")?;
+        let body = HeartbeatRequest {
+            name: self.request.name.clone(),
+        };
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(url.as_str())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&body)?))?;
+        let response: HeartbeatResponse = self.send(req).await?;
+        Ok(response.registered)
+    }
+
+    /// Register the plugin and keep sending heartbeats, registering again if the hub forgot
+    /// about the plugin
+    ///
+    /// This function never returns: it's meant to be spawned and aborted at shutdown
+    pub async fn run(&self) {
+        let mut heartbeat_interval = self.register_with_retry().await;
+        loop {
+            tokio::time::sleep(heartbeat_interval).await;
+            match self.heartbeat().await {
+                Ok(true) => (),
+                Ok(false) => {
+                    warn!(
+                        "aide doesn't know {} anymore, registering again",
+                        self.get_name()
+                    );
+                    heartbeat_interval = self.register_with_retry().await;
+                }
+                Err(e) => warn!("Heartbeat failed [{}]", e),
+            }
+        }
+    }
+
+    /// Remove the plugin from the hub
+    pub async fn deregister(&self) -> Result<(), RegistrationError> {
+        let url = self.module_url()?;
+        let req = Request::builder()
+            .method(Method::DELETE)
+            .uri(url.as_str())
+            .body(Body::empty())?;
+        let _response: DeregisterModuleResponse = self.send(req).await?;
+        Ok(())
+    }
+
+    /// The URL of the plugin in the hub, with the name percent-encoded
+    fn module_url(&self) -> Result<Url, RegistrationError> {
+        let mut url = self.hub_url.join("v1/modules")?;
+        url.path_segments_mut()
+            .map_err(|_| url::ParseError::RelativeUrlWithCannotBeABaseBase)?
+            .push(&self.request.name);
+        Ok(url)
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        mut req: Request<Body>,
+    ) -> Result<T, RegistrationError> {
+        if let Some(auth) = &self.auth {
+            req.headers_mut().insert(AUTHORIZATION, auth.clone());
+        }
+        tokio::time::timeout(REQUEST_TIMEOUT, exchange(req))
+            .await
+            .map_err(|_| RegistrationError::Timeout(REQUEST_TIMEOUT))?
+    }
+}
+
+/// Send the request to the hub and parse its reply
+async fn exchange<T: DeserializeOwned>(req: Request<Body>) -> Result<T, RegistrationError> {
+    let client = Client::new();
+    let res = client.request(req).await?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    if status != StatusCode::OK {
+        return Err(RegistrationError::Refused(
+            status,
+            String::from_utf8_lossy(&body).to_string(),
+        ));
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+fn heartbeat_interval(response: &RegisterModuleResponse) -> Duration {
+    Duration::from_secs(response.heartbeat_interval).max(MIN_HEARTBEAT_INTERVAL)
+}
+
+/// Whether the URL points to this host
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(d)) => d == "localhost",
+        Some(url::Host::Ipv4(a)) => a.is_loopback(),
+        Some(url::Host::Ipv6(a)) => a.is_loopback(),
+        None => false,
+    }
+}
+
+/// A future completing when the process receives SIGINT or SIGTERM
+///
+/// It's meant to be passed to hyper's `with_graceful_shutdown()`
+pub async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn registration_from_opt() {
+        let opt = CommonOpt::parse_from(["test", "-R", "-H", "0.0.0.0", "-p", "8080"]);
+        let uut = Registration::new(&opt, "habitica", ModuleKind::Todo).unwrap();
+        assert_eq!(uut.get_name(), "habitica");
+        assert_eq!(uut.request.kind, ModuleKind::Todo);
+        assert_eq!(uut.request.callback.as_str(), "http://127.0.0.1:8080/");
+        assert_eq!(uut.request.api_version, API_VERSION);
+        assert_eq!(uut.hub_url.as_str(), "http://127.0.0.1:9090/");
+    }
+
+    #[test]
+    fn registration_with_name() {
+        let opt = CommonOpt::parse_from([
+            "test",
+            "-R",
+            "--name",
+            "work",
+            "--hub",
+            "http://hub:9090/",
+            "-p",
+            "8080",
+        ]);
+        let uut = Registration::new(&opt, "habitica", ModuleKind::Todo).unwrap();
+        assert_eq!(uut.get_name(), "work");
+        assert_eq!(uut.hub_url.as_str(), "http://hub:9090/");
+        assert_eq!(
+            uut.module_url().unwrap().as_str(),
+            "http://hub:9090/v1/modules/work"
+        );
+        let opt = CommonOpt::parse_from(["test", "-R", "--name", "my/work?"]);
+        let uut = Registration::new(&opt, "habitica", ModuleKind::Todo).unwrap();
+        assert_eq!(
+            uut.module_url().unwrap().as_str(),
+            "http://127.0.0.1:9090/v1/modules/my%2Fwork%3F"
+        );
+    }
+
+    #[test]
+    fn registration_with_invalid_token() {
+        let opt = CommonOpt::parse_from(["test", "-R", "--token", "bad\ntoken"]);
+        assert!(matches!(
+            Registration::new(&opt, "habitica", ModuleKind::Todo),
+            Err(RegistrationError::Token(_))
+        ));
+    }
+
+    #[test]
+    fn heartbeat_interval_from_hub() {
+        let response = |heartbeat_interval| RegisterModuleResponse {
+            success: true,
+            heartbeat_interval,
+        };
+        assert_eq!(heartbeat_interval(&response(30)), Duration::from_secs(30));
+        assert_eq!(heartbeat_interval(&response(0)), MIN_HEARTBEAT_INTERVAL);
+    }
+
+    #[test]
+    fn registration_to_remote_hub() {
+        let args = ["test", "-R", "--hub", "http://hub:9090/", "-H", "0.0.0.0"];
+        let opt = CommonOpt::parse_from(args);
+        assert!(matches!(
+            Registration::new(&opt, "habitica", ModuleKind::Todo),
+            Err(RegistrationError::UnreachableCallback(_))
+        ));
+        let opt = CommonOpt::parse_from(args.iter().chain(&["--advertise-addr", "192.168.1.2"]));
+        let uut = Registration::new(&opt, "habitica", ModuleKind::Todo).unwrap();
+        assert_eq!(uut.request.callback.as_str(), "http://192.168.1.2/");
+    }
+}
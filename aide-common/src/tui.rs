@@ -17,6 +17,10 @@ use tui::{
 
 pub trait ToStringVec {
     fn to_string_vec(&self) -> Vec<String>;
+    /// The colors of the items, if they have to stand out; missing items use the default
+    fn to_color_vec(&self) -> Vec<Option<Color>> {
+        Vec::new()
+    }
 }
 
 pub trait ToListState {
@@ -32,8 +36,9 @@ pub fn draw_list<B: Backend, T: ToStringVec + ToListState + GetTitle>(
     object: &mut T,
 ) {
     let sv = object.to_string_vec();
+    let colors = object.to_color_vec();
     let list = sv
-        .to_list()
+        .to_list(&colors)
         .block(
             Block::default()
                 .title(object.get_title())
@@ -78,14 +83,18 @@ pub fn tui_teardown<B: Backend + Write>(terminal: &mut Terminal<B>) -> Result<()
     Ok(())
 }
 trait ToList {
-    fn to_list(&self) -> List;
+    fn to_list(&self, colors: &[Option<Color>]) -> List<'_>;
 }
 
 impl ToList for Vec<String> {
-    fn to_list(&self) -> List<'_> {
+    fn to_list(&self, colors: &[Option<Color>]) -> List<'_> {
         List::new(
             self.iter()
-                .map(|s| ListItem::new(s.as_str()))
+                .enumerate()
+                .map(|(i, s)| match colors.get(i).copied().flatten() {
+                    Some(c) => ListItem::new(s.as_str()).style(Style::default().fg(c)),
+                    None => ListItem::new(s.as_str()),
+                })
                 .collect::<Vec<_>>(),
         )
     }
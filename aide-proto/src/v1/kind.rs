@@ -1,7 +1,19 @@
 use serde::{Deserialize, Serialize};
-use strum_macros::{EnumString, EnumVariantNames};
+use strum_macros::{Display, EnumString, EnumVariantNames};
 
-#[derive(Debug, PartialEq, EnumString, EnumVariantNames)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Display,
+    Eq,
+    Hash,
+    PartialEq,
+    EnumString,
+    EnumVariantNames,
+    Deserialize,
+    Serialize,
+)]
 #[strum(ascii_case_insensitive)]
 pub enum ModuleKind {
     Todo,
@@ -42,4 +54,14 @@ mod tests {
             ModuleKind::from_str("weather").unwrap()
         );
     }
+    #[test]
+    fn module_kind_serde() {
+        assert_eq!(
+            serde_json::to_string(&ModuleKind::Todo).unwrap(),
+            r#""Todo""#
+        );
+        let uut: ModuleKind = serde_json::from_str(r#""Weather""#).unwrap();
+        assert_eq!(uut, ModuleKind::Weather);
+        assert!(serde_json::from_str::<ModuleKind>(r#""Unknown""#).is_err());
+    }
 }
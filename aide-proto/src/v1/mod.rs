@@ -19,8 +19,23 @@ pub mod kind;
 pub use kind::GetModuleKindResponse;
 pub use kind::ModuleKind;
 
+pub mod module;
+pub use module::DeregisterModuleResponse;
+pub use module::HeartbeatRequest;
+pub use module::HeartbeatResponse;
+pub use module::ModuleHealth;
+pub use module::ModuleInfo;
+pub use module::ModuleListResponse;
+pub use module::RegisterModuleRequest;
+pub use module::RegisterModuleResponse;
+pub use module::API_VERSION;
+
 pub mod todo;
+pub use todo::DueDate;
 pub use todo::Todo;
+pub use todo::TodoListResponse;
+pub use todo::TodoQuery;
+pub use todo::TodoUpdate;
 
 pub mod weather;
 pub use weather::CurrentWeather;
@@ -0,0 +1,187 @@
+use super::ModuleKind;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The version of the protocol spoken by modules implementing this API
+pub const API_VERSION: &str = "v1";
+
+fn default_api_version() -> String {
+    API_VERSION.to_string()
+}
+
+/// The payload a plugin sends to `POST /v1/modules` to register to the hub
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RegisterModuleRequest {
+    pub name: String,
+    pub kind: ModuleKind,
+    /// The base URL where the plugin can be reached by the hub
+    pub callback: Url,
+    #[serde(default = "default_api_version")]
+    pub api_version: String,
+}
+
+/// The reply of the hub to a successful registration
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RegisterModuleResponse {
+    pub success: bool,
+    /// How often, in seconds, the hub expects a heartbeat from the plugin
+    pub heartbeat_interval: u64,
+}
+
+/// The reply of the hub to `DELETE /v1/modules/:name`, containing the removed module
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DeregisterModuleResponse {
+    pub success: bool,
+    pub module: ModuleInfo,
+}
+
+/// The payload a plugin periodically sends to `POST /v1/heartbeat`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct HeartbeatRequest {
+    pub name: String,
+}
+
+/// The reply to an heartbeat
+///
+/// If `registered` is false, the hub doesn't know the plugin (i.e. the hub has been restarted)
+/// and the plugin has to register again
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct HeartbeatResponse {
+    pub registered: bool,
+}
+
+/// The health of a module, as monitored by the hub
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum ModuleHealth {
+    #[default]
+    Healthy,
+    Unhealthy,
+}
+
+/// A module, as known by the hub
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub kind: ModuleKind,
+    pub callback: Url,
+    #[serde(default = "default_api_version")]
+    pub api_version: String,
+    #[serde(default)]
+    pub health: ModuleHealth,
+    /// The last time the hub heard from the module (seconds since the Unix epoch)
+    #[serde(default)]
+    pub last_seen: Option<u64>,
+}
+
+impl From<RegisterModuleRequest> for ModuleInfo {
+    fn from(req: RegisterModuleRequest) -> Self {
+        ModuleInfo {
+            name: req.name,
+            kind: req.kind,
+            callback: req.callback,
+            api_version: req.api_version,
+            health: ModuleHealth::Healthy,
+            last_seen: None,
+        }
+    }
+}
+
+/// The reply to `GET /v1/modules`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModuleListResponse {
+    pub data: Vec<ModuleInfo>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module_info() -> ModuleInfo {
+        ModuleInfo {
+            name: "habitica".to_string(),
+            kind: ModuleKind::Todo,
+            callback: Url::parse("http://127.0.0.1:8080/").unwrap(),
+            api_version: API_VERSION.to_string(),
+            health: ModuleHealth::Unhealthy,
+            last_seen: Some(1_650_000_000),
+        }
+    }
+
+    #[test]
+    fn register_request_round_trip() {
+        let uut = RegisterModuleRequest {
+            name: "weatherapi".to_string(),
+            kind: ModuleKind::Weather,
+            callback: Url::parse("http://127.0.0.1:8081/").unwrap(),
+            api_version: API_VERSION.to_string(),
+        };
+        let json = serde_json::to_string(&uut).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"weatherapi","kind":"Weather","callback":"http://127.0.0.1:8081/","api_version":"v1"}"#
+        );
+        let got: RegisterModuleRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(got, uut);
+    }
+
+    #[test]
+    fn register_request_default_api_version() {
+        let input = r#"{"name":"habitica","kind":"Todo","callback":"http://127.0.0.1:8080/"}"#;
+        let got: RegisterModuleRequest = serde_json::from_str(input).unwrap();
+        assert_eq!(got.api_version, API_VERSION);
+        let input = r#"{"name":"habitica","kind":"Todo","callback":"not an url"}"#;
+        assert!(serde_json::from_str::<RegisterModuleRequest>(input).is_err());
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let uut = RegisterModuleResponse {
+            success: true,
+            heartbeat_interval: 30,
+        };
+        let got: RegisterModuleResponse =
+            serde_json::from_str(&serde_json::to_string(&uut).unwrap()).unwrap();
+        assert_eq!(got, uut);
+
+        let uut = DeregisterModuleResponse {
+            success: true,
+            module: module_info(),
+        };
+        let got: DeregisterModuleResponse =
+            serde_json::from_str(&serde_json::to_string(&uut).unwrap()).unwrap();
+        assert_eq!(got, uut);
+    }
+
+    #[test]
+    fn heartbeat_round_trip() {
+        let uut = HeartbeatRequest {
+            name: "habitica".to_string(),
+        };
+        let got: HeartbeatRequest =
+            serde_json::from_str(&serde_json::to_string(&uut).unwrap()).unwrap();
+        assert_eq!(got, uut);
+
+        let uut = HeartbeatResponse { registered: false };
+        let got: HeartbeatResponse =
+            serde_json::from_str(&serde_json::to_string(&uut).unwrap()).unwrap();
+        assert_eq!(got, uut);
+    }
+
+    #[test]
+    fn module_info_defaults() {
+        let input = r#"{"name":"habitica","kind":"Todo","callback":"http://127.0.0.1:8080/"}"#;
+        let got: ModuleInfo = serde_json::from_str(input).unwrap();
+        assert_eq!(got.health, ModuleHealth::Healthy);
+        assert!(got.last_seen.is_none());
+    }
+
+    #[test]
+    fn module_list_round_trip() {
+        let uut = ModuleListResponse {
+            data: vec![module_info()],
+        };
+        let got: ModuleListResponse =
+            serde_json::from_str(&serde_json::to_string(&uut).unwrap()).unwrap();
+        assert_eq!(got, uut);
+    }
+}
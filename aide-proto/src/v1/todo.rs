@@ -1,44 +1,156 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate};
 use serde::{Deserialize, Serialize};
-use strum_macros::{EnumString, EnumVariantNames};
+use strum_macros::{Display, EnumString, EnumVariantNames};
 
-#[derive(Clone, Copy, Debug, PartialEq, EnumString, EnumVariantNames, Deserialize, Serialize)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    PartialEq,
+    EnumString,
+    EnumVariantNames,
+    Deserialize,
+    Serialize,
+)]
 #[strum(ascii_case_insensitive)]
 pub enum TodoTypes {
+    #[default]
     Task,
     Daily,
     Weekly,
+    /// Something to do or avoid regularly, scored up or down
+    Habit,
+    /// Something to buy, with a cost
+    Reward,
 }
 
-impl Default for TodoTypes {
-    fn default() -> Self {
-        TodoTypes::Task
+impl TodoTypes {
+    /// Habits and rewards are listed only if explicitly requested
+    pub fn is_listed_by_default(&self) -> bool {
+        !matches!(self, TodoTypes::Habit | TodoTypes::Reward)
     }
 }
 
-#[derive(Debug, PartialEq, EnumString, EnumVariantNames)]
+/// The counters of a habit, scored up when done and down when failed
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct HabitCounters {
+    /// Whether the habit can be scored up
+    pub up: bool,
+    /// Whether the habit can be scored down
+    pub down: bool,
+    pub counter_up: u32,
+    pub counter_down: u32,
+}
+
+#[derive(Debug, Default, PartialEq, EnumString, EnumVariantNames)]
 #[strum(ascii_case_insensitive)]
 pub enum Day {
     Monday,
     Tuesday,
+    #[default]
     Sunday,
 }
 
-impl Default for Day {
-    fn default() -> Self {
-        Day::Sunday
+/// The due date of a todo, with or without a time
+///
+/// It's serialized as an ISO-8601 string: `2021-03-01` or `2021-03-01T18:00:00+01:00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DueDate {
+    Date(NaiveDate),
+    DateTime(DateTime<FixedOffset>),
+}
+
+impl DueDate {
+    /// The day the todo is due, in the local timezone
+    pub fn date(&self) -> NaiveDate {
+        match self {
+            DueDate::Date(d) => *d,
+            DueDate::DateTime(dt) => dt.with_timezone(&Local).date_naive(),
+        }
+    }
+}
+
+impl From<NaiveDate> for DueDate {
+    fn from(d: NaiveDate) -> Self {
+        DueDate::Date(d)
+    }
+}
+
+impl std::fmt::Display for DueDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DueDate::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            DueDate::DateTime(dt) => write!(f, "{}", dt.to_rfc3339()),
+        }
+    }
+}
+
+impl std::str::FromStr for DueDate {
+    type Err = chrono::ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            Ok(d) => Ok(DueDate::Date(d)),
+            Err(_) => DateTime::parse_from_rfc3339(s).map(DueDate::DateTime),
+        }
+    }
+}
+
+impl Serialize for DueDate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DueDate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 
-// TODO convert due_date in a proper type
+/// Deserialize a due date that can't be parsed, like one written by an older version, as
+/// no due date, instead of failing the whole todo
+fn lenient_due_date<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DueDate>, D::Error> {
+    let s = Option::<String>::deserialize(deserializer)?;
+    Ok(s.and_then(|s| s.parse().ok()))
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Todo {
+    /// The identifier of the todo, opaque and unique only within its source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     pub descr: Option<String>,
     pub tags: Vec<String>,
     pub todo_type: TodoTypes,
     pub checklist: Vec<CheckListItem>,
-    pub due_date: Option<String>,
+    #[serde(default, deserialize_with = "lenient_due_date")]
+    pub due_date: Option<DueDate>,
     pub done: bool,
+    /// The name of the module that provided the todo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The counters, only for habits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub habit: Option<HabitCounters>,
+    /// The cost, only for rewards
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// When the todo was completed, only for completed todos
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<FixedOffset>>,
+}
+
+impl Todo {
+    /// True if the todo is not done and it was due before `today`
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        !self.done && self.due_date.is_some_and(|d| d.date() < today)
+    }
 }
 
 impl std::fmt::Display for Todo {
@@ -51,8 +163,14 @@ impl std::fmt::Display for Todo {
             self.tags.iter().for_each(|t| write!(f, "{} ", t).unwrap());
             writeln!(f)?;
         }
-        if self.due_date.is_some() {
-            writeln!(f, "  due date: {}", self.due_date.clone().unwrap())?;
+        if let Some(due_date) = &self.due_date {
+            writeln!(f, "  due date: {}", due_date)?;
+        }
+        if let Some(habit) = &self.habit {
+            writeln!(f, "  score: +{} -{}", habit.counter_up, habit.counter_down)?;
+        }
+        if let Some(cost) = self.cost {
+            writeln!(f, "  cost: {}", cost)?;
         }
         if !self.checklist.is_empty() {
             writeln!(f, "  checklist:")?;
@@ -75,6 +193,9 @@ impl From<String> for Todo {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CheckListItem {
+    /// The identifier of the item, opaque and unique only within its todo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     pub done: bool,
 }
@@ -89,6 +210,204 @@ impl std::fmt::Display for CheckListItem {
     }
 }
 
+/// The body of `PUT /v1/todos/:id`
+///
+/// Only the fields that are present are updated. `tags` and `checklist` replace the
+/// current ones, an empty `tags` removing all the labels: checklist items with an `id`
+/// update the existing item, items without it keep the existing item with the same name
+/// or are added, and existing items missing from the list are removed
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TodoUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub descr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checklist: Option<Vec<CheckListItem>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<DueDate>,
+    /// Remove the due date; it can't be used together with `due_date`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub clear_due_date: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, EnumString)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum LabelMode {
+    /// Todos with at least one of the labels
+    #[default]
+    Any,
+    /// Todos with all the labels
+    All,
+}
+
+/// The filters of `GET /v1/todos`, as query parameters
+///
+/// `type=daily,weekly&label=a,b&label_mode=all&done=false&due_before=2021-03-01&q=milk`
+///
+/// Missing parameters don't filter anything and unknown parameters are ignored
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TodoQuery {
+    /// Any of these types; if empty, all types but habits and rewards
+    pub types: Vec<TodoTypes>,
+    pub labels: Vec<String>,
+    pub label_mode: LabelMode,
+    pub done: Option<bool>,
+    /// Only todos due strictly before this day
+    pub due_before: Option<DueDate>,
+    /// Case insensitive substring of the name or of the description
+    pub q: Option<String>,
+    /// Only todos completed on this day or later
+    pub since: Option<DueDate>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub param: String,
+    pub value: String,
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid value {} for parameter {}",
+            self.value, self.param
+        )
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl TodoQuery {
+    pub fn from_query(query: Option<&str>) -> Result<Self, QueryError> {
+        let mut result = TodoQuery::default();
+        let query = match query {
+            Some(q) => q,
+            None => return Ok(result),
+        };
+        for (k, v) in url::form_urlencoded::parse(query.as_bytes()) {
+            let error = || QueryError {
+                param: k.to_string(),
+                value: v.to_string(),
+            };
+            match k.as_ref() {
+                "type" => {
+                    for t in v.split(',').filter(|t| !t.is_empty()) {
+                        result.types.push(t.parse().map_err(|_| error())?);
+                    }
+                }
+                "label" => result.labels.extend(
+                    v.split(',')
+                        .filter(|l| !l.is_empty())
+                        .map(|l| l.to_string()),
+                ),
+                "label_mode" => result.label_mode = v.parse().map_err(|_| error())?,
+                "done" => result.done = Some(v.parse().map_err(|_| error())?),
+                "due_before" => result.due_before = Some(v.parse().map_err(|_| error())?),
+                "q" => result.q = Some(v.to_string()),
+                "since" => result.since = Some(v.parse().map_err(|_| error())?),
+                _ => (),
+            }
+        }
+        Ok(result)
+    }
+
+    /// The query string, empty if there are no filters
+    pub fn to_query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        if !self.types.is_empty() {
+            let types: Vec<String> = self.types.iter().map(|t| t.to_string()).collect();
+            serializer.append_pair("type", &types.join(","));
+        }
+        if !self.labels.is_empty() {
+            serializer.append_pair("label", &self.labels.join(","));
+            if self.label_mode != LabelMode::default() {
+                serializer.append_pair("label_mode", &self.label_mode.to_string());
+            }
+        }
+        if let Some(done) = self.done {
+            serializer.append_pair("done", &done.to_string());
+        }
+        if let Some(due_before) = self.due_before {
+            serializer.append_pair("due_before", &due_before.to_string());
+        }
+        if let Some(q) = &self.q {
+            serializer.append_pair("q", q);
+        }
+        if let Some(since) = self.since {
+            serializer.append_pair("since", &since.to_string());
+        }
+        serializer.finish()
+    }
+
+    /// True if the todo passes all the filters
+    pub fn matches(&self, todo: &Todo) -> bool {
+        if self.types.is_empty() {
+            if !todo.todo_type.is_listed_by_default() {
+                return false;
+            }
+        } else if !self.types.contains(&todo.todo_type) {
+            return false;
+        }
+        if !self.labels.is_empty() {
+            let mut labels = self.labels.iter();
+            let found = match self.label_mode {
+                LabelMode::Any => labels.any(|l| todo.tags.contains(l)),
+                LabelMode::All => labels.all(|l| todo.tags.contains(l)),
+            };
+            if !found {
+                return false;
+            }
+        }
+        if self.done.is_some_and(|d| d != todo.done) {
+            return false;
+        }
+        if let Some(due_before) = self.due_before {
+            if todo.due_date.is_none_or(|d| d.date() >= due_before.date()) {
+                return false;
+            }
+        }
+        if let Some(q) = &self.q {
+            let q = q.to_lowercase();
+            let in_descr = todo
+                .descr
+                .as_ref()
+                .is_some_and(|d| d.to_lowercase().contains(&q));
+            if !todo.name.to_lowercase().contains(&q) && !in_descr {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            let completed_on = todo
+                .completed_at
+                .map(|c| c.with_timezone(&Local).date_naive());
+            if completed_on.is_none_or(|c| c < since.date()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The reply of the hub to `GET /v1/todos`, merging the todos of all todo modules
+///
+/// Modules that failed to reply are reported in `errors`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TodoListResponse {
+    pub data: Vec<Todo>,
+    #[serde(default)]
+    pub errors: Vec<ModuleError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ModuleError {
+    pub module: String,
+    pub error: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Label {
     pub name: String,
@@ -96,7 +415,7 @@ pub struct Label {
 
 #[cfg(test)]
 mod tests {
-    use super::TodoTypes;
+    use super::{DueDate, LabelMode, Todo, TodoQuery, TodoTypes};
     use std::str::FromStr;
     #[test]
     fn todo_type_from_str() {
@@ -105,4 +424,128 @@ mod tests {
         let uut: TodoTypes = TodoTypes::from_str("task").unwrap();
         assert_eq!(TodoTypes::Task, uut);
     }
+    #[test]
+    fn todo_with_id() {
+        let mut uut = Todo::from("t".to_string());
+        uut.id = Some("abc".to_string());
+        uut.source = Some("habitica".to_string());
+        let json = serde_json::to_string(&uut).unwrap();
+        assert!(json.starts_with(r#"{"id":"abc","name":"t""#));
+        let got: Todo = serde_json::from_str(&json).unwrap();
+        assert_eq!(got.id.as_deref(), Some("abc"));
+        assert_eq!(got.source.as_deref(), Some("habitica"));
+    }
+    #[test]
+    fn todo_update_partial() {
+        let uut: super::TodoUpdate = serde_json::from_str(r#"{"name":"new"}"#).unwrap();
+        assert_eq!(uut.name.as_deref(), Some("new"));
+        assert!(uut.tags.is_none());
+        assert!(uut.checklist.is_none());
+        assert_eq!(serde_json::to_string(&uut).unwrap(), r#"{"name":"new"}"#);
+        let uut: super::TodoUpdate = serde_json::from_str(r#"{"clear_due_date":true}"#).unwrap();
+        assert!(uut.clear_due_date);
+        assert!(uut.due_date.is_none());
+        assert_eq!(
+            serde_json::to_string(&uut).unwrap(),
+            r#"{"clear_due_date":true}"#
+        );
+    }
+    #[test]
+    fn due_date_serde() {
+        let input = r#"{"name":"t","descr":null,"tags":[],"todo_type":"Task","checklist":[],"due_date":"2021-03-01","done":false}"#;
+        let uut: Todo = serde_json::from_str(input).unwrap();
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 3, 1).unwrap();
+        assert_eq!(uut.due_date, Some(DueDate::Date(date)));
+        assert_eq!(serde_json::to_string(&uut).unwrap(), input);
+        let uut: DueDate = serde_json::from_str(r#""2021-03-01T18:30:00+01:00""#).unwrap();
+        assert!(matches!(uut, DueDate::DateTime(_)));
+        assert_eq!(
+            serde_json::to_string(&uut).unwrap(),
+            r#""2021-03-01T18:30:00+01:00""#
+        );
+        assert!(serde_json::from_str::<DueDate>(r#""tomorrow""#).is_err());
+    }
+    #[test]
+    fn todo_with_invalid_due_date() {
+        let input = r#"{"name":"t","descr":null,"tags":[],"todo_type":"Task","checklist":[],"due_date":"Mon Mar 01 2021","done":false}"#;
+        let uut: Todo = serde_json::from_str(input).unwrap();
+        assert!(uut.due_date.is_none());
+        let input = r#"[{"name":"t","descr":null,"tags":[],"todo_type":"Task","checklist":[],"due_date":"2021-03-01","done":false},
+            {"name":"u","descr":null,"tags":[],"todo_type":"Task","checklist":[],"due_date":"","done":false},
+            {"name":"v","descr":null,"tags":[],"todo_type":"Task","checklist":[],"done":false}]"#;
+        let uut: Vec<Todo> = serde_json::from_str(input).unwrap();
+        assert_eq!(uut.len(), 3);
+        assert!(uut[0].due_date.is_some());
+        assert!(uut[1].due_date.is_none());
+        assert!(uut[2].due_date.is_none());
+    }
+    #[test]
+    fn todo_overdue() {
+        let today = chrono::NaiveDate::from_ymd_opt(2021, 3, 2).unwrap();
+        let mut uut = Todo::from("t".to_string());
+        assert!(!uut.is_overdue(today));
+        uut.due_date = Some(DueDate::from(today));
+        assert!(!uut.is_overdue(today));
+        uut.due_date = today.pred_opt().map(DueDate::from);
+        assert!(uut.is_overdue(today));
+        uut.done = true;
+        assert!(!uut.is_overdue(today));
+    }
+    #[test]
+    fn todo_query_parse() {
+        let uut = TodoQuery::from_query(Some(
+            "type=daily,Weekly&label=a,b&label_mode=all&done=false&due_before=2021-03-01&q=milk&since=2021-02-22&source=x",
+        ))
+        .unwrap();
+        assert_eq!(uut.types, vec![TodoTypes::Daily, TodoTypes::Weekly]);
+        assert_eq!(uut.labels, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(uut.label_mode, LabelMode::All);
+        assert_eq!(uut.done, Some(false));
+        assert!(uut.due_before.is_some());
+        assert_eq!(uut.q.as_deref(), Some("milk"));
+        assert!(uut.since.is_some());
+        assert_eq!(TodoQuery::from_query(Some(&uut.to_query())).unwrap(), uut);
+        assert_eq!(TodoQuery::from_query(None).unwrap(), TodoQuery::default());
+        assert_eq!(TodoQuery::default().to_query(), "");
+        let err = TodoQuery::from_query(Some("type=monthly")).unwrap_err();
+        assert_eq!(err.param, "type");
+        assert!(TodoQuery::from_query(Some("done=maybe")).is_err());
+    }
+    #[test]
+    fn todo_query_matches() {
+        let mut todo = Todo::from("Buy Milk".to_string());
+        todo.tags = vec!["a".to_string(), "b".to_string()];
+        todo.due_date = "2021-03-01".parse().ok();
+        let query = |q: &str| TodoQuery::from_query(Some(q)).unwrap();
+        assert!(query("").matches(&todo));
+        assert!(query("type=task").matches(&todo));
+        assert!(!query("type=daily,weekly").matches(&todo));
+        todo.todo_type = TodoTypes::Habit;
+        assert!(!query("").matches(&todo));
+        assert!(query("type=habit").matches(&todo));
+        todo.todo_type = TodoTypes::Task;
+        assert!(query("label=a,c").matches(&todo));
+        assert!(!query("label=a,c&label_mode=all").matches(&todo));
+        assert!(query("label=a,b&label_mode=all").matches(&todo));
+        assert!(query("done=false").matches(&todo));
+        assert!(!query("done=true").matches(&todo));
+        assert!(query("due_before=2021-03-02").matches(&todo));
+        assert!(!query("due_before=2021-03-01").matches(&todo));
+        assert!(query("q=milk").matches(&todo));
+        assert!(!query("q=bread").matches(&todo));
+        todo.due_date = None;
+        assert!(!query("due_before=2021-03-02").matches(&todo));
+        assert!(!query("since=2021-03-01").matches(&todo));
+        todo.completed_at = chrono::DateTime::parse_from_rfc3339("2021-03-01T12:00:00+00:00").ok();
+        assert!(query("since=2021-02-28").matches(&todo));
+        assert!(!query("since=2021-03-03").matches(&todo));
+    }
+    #[test]
+    fn todo_without_source() {
+        let input = r#"{"name":"t","descr":null,"tags":[],"todo_type":"Task","checklist":[],"due_date":null,"done":false}"#;
+        let uut: Todo = serde_json::from_str(input).unwrap();
+        assert!(uut.id.is_none());
+        assert!(uut.source.is_none());
+        assert_eq!(serde_json::to_string(&uut).unwrap(), input);
+    }
 }
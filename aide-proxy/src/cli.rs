@@ -0,0 +1,33 @@
+use clap::Parser;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use url::Url;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(author, version, about)]
+pub struct Opt {
+    #[clap(name = "host", long = "host", short = 'H', default_value = "127.0.0.1")]
+    /// Set the listening IP address
+    pub host_addr: IpAddr,
+
+    #[clap(short = 'p', long, default_value_t = 443)]
+    /// Listening TCP port of the proxy
+    pub port: u16,
+
+    #[clap(short = 'c', long, parse(from_os_str))]
+    /// The PEM file containing the TLS certificate chain
+    pub cert: PathBuf,
+
+    #[clap(short = 'k', long, parse(from_os_str))]
+    /// The PEM file containing the TLS private key
+    pub key: PathBuf,
+
+    #[clap(short = 'u', long, default_value = "http://127.0.0.1:9090/")]
+    /// The URL of the upstream server (the aide hub or a plugin), ignored if a route table
+    /// is provided
+    pub upstream: Url,
+
+    #[clap(short = 'C', long, parse(from_os_str))]
+    /// The TOML file with the route table, reloaded on SIGHUP
+    pub config: Option<PathBuf>,
+}
@@ -1,3 +1,132 @@
-fn main() {
-    println!("Hello, world!");
+mod cli;
+mod routes;
+mod tls;
+
+use aide_common::proxy::HttpClient;
+use aide_common::{http_404, http_502};
+use clap::Parser;
+use hyper::header::HeaderValue;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use log::{debug, info, warn};
+use routes::RouteTable;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// The pause after a failed accept, like when out of file descriptors, to give the open
+/// connections time to close
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+struct State {
+    routes: Arc<RwLock<RouteTable>>,
+    client: HttpClient,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    env_logger::try_init()
+        .unwrap_or_else(|e| eprintln!("Failed to initialize env_logger [{:?}]", e));
+    let opt = cli::Opt::parse();
+    let acceptor = TlsAcceptor::from(tls::server_config(&opt.cert, &opt.key)?);
+    let routes = match &opt.config {
+        Some(path) => RouteTable::load(path)?,
+        None => RouteTable {
+            default: Some(opt.upstream.clone()),
+            ..RouteTable::default()
+        },
+    };
+    let state = State {
+        routes: Arc::new(RwLock::new(routes)),
+        client: HttpClient::new(),
+    };
+    if let Some(path) = opt.config.clone() {
+        tokio::spawn(reload_on_sighup(path, state.routes.clone()));
+    }
+
+    let socket_addr = SocketAddr::new(opt.host_addr, opt.port);
+    let listener = TcpListener::bind(&socket_addr).await?;
+    let shutdown = aide_common::shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept a connection [{}]", e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let acceptor = acceptor.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            let tls_stream = match acceptor.accept(stream).await {
+                Ok(s) => s,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed [{}]", peer, e);
+                    return;
+                }
+            };
+            let service = service_fn(move |req| proxy_svc(req, state.clone(), peer));
+            if let Err(e) = Http::new().serve_connection(tls_stream, service).await {
+                debug!("Connection with {} failed [{}]", peer, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn reload_on_sighup(path: PathBuf, routes: Arc<RwLock<RouteTable>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+    while sighup.recv().await.is_some() {
+        match RouteTable::load(&path) {
+            Ok(new_routes) => {
+                *routes.write().unwrap() = new_routes;
+                info!("Route table reloaded from {}", path.display());
+            }
+            Err(e) => warn!("Route table not reloaded, keeping the old one [{:#}]", e),
+        }
+    }
+}
+
+async fn proxy_svc(
+    mut req: Request<Body>,
+    state: State,
+    peer: SocketAddr,
+) -> Result<Response<Body>, anyhow::Error> {
+    let resolved = state.routes.read().unwrap().resolve(req.uri().path());
+    let (upstream, path) = match resolved {
+        Some(r) => r,
+        None => return Ok(http_404(&"No route found")),
+    };
+    let path_and_query = match req.uri().query() {
+        Some(q) => format!("{}?{}", path, q),
+        None => path,
+    };
+    *req.uri_mut() = match path_and_query.parse() {
+        Ok(uri) => uri,
+        Err(e) => {
+            warn!("Invalid upstream path {} [{}]", path_and_query, e);
+            return Ok(http_502(&"Invalid upstream path"));
+        }
+    };
+    let headers = req.headers_mut();
+    aide_common::proxy::append_forwarded_for(headers, peer.ip());
+    headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+    match aide_common::proxy::forward(&state.client, &upstream, req).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Failed to forward the request to {} [{}]", upstream, e);
+            Ok(http_502(&"Upstream not reachable"))
+        }
+    }
 }
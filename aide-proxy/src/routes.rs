@@ -0,0 +1,163 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+use url::Url;
+
+/// A route of the proxy, mapping a path prefix to an upstream server
+///
+/// ```toml
+/// [[routes]]
+/// prefix = "/healthz/habitica"
+/// upstream = "http://127.0.0.1:8080/"
+/// rewrite = "/healthz"
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Route {
+    pub prefix: String,
+    pub upstream: Url,
+    /// If present, the matched prefix is replaced by this before forwarding
+    pub rewrite: Option<String>,
+}
+
+/// The route table, loaded from a TOML file
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct RouteTable {
+    /// The upstream server used when no route matches
+    pub default: Option<Url>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+}
+
+impl RouteTable {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let table: RouteTable = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(table)
+    }
+
+    /// Find the upstream server for the path, returning it with the path to be requested
+    ///
+    /// When more routes match, the one with the longest prefix wins
+    pub fn resolve(&self, path: &str) -> Option<(Url, String)> {
+        let route = self
+            .routes
+            .iter()
+            .filter(|r| {
+                let prefix = r.prefix.trim_end_matches('/');
+                path.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|r| r.prefix.trim_end_matches('/').len());
+        match route {
+            Some(r) => {
+                let upstream_path = match &r.rewrite {
+                    Some(rewrite) => {
+                        let rest = &path[r.prefix.trim_end_matches('/').len()..];
+                        let rewritten = format!("{}{}", rewrite.trim_end_matches('/'), rest);
+                        // the upstream path has to be absolute, even if empty
+                        if rewritten.starts_with('/') {
+                            rewritten
+                        } else {
+                            format!("/{}", rewritten)
+                        }
+                    }
+                    None => path.to_string(),
+                };
+                Some((r.upstream.clone(), upstream_path))
+            }
+            None => self.default.clone().map(|u| (u, path.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default = "http://127.0.0.1:9090/"
+
+[[routes]]
+prefix = "/v1/todos"
+upstream = "http://127.0.0.1:8080/"
+
+[[routes]]
+prefix = "/v1/current"
+upstream = "http://127.0.0.1:8081/"
+
+[[routes]]
+prefix = "/healthz/habitica"
+upstream = "http://127.0.0.1:8080/"
+rewrite = "/healthz"
+"#;
+
+    #[test]
+    fn parse_config() {
+        let uut: RouteTable = toml::from_str(CONFIG).unwrap();
+        assert_eq!(uut.routes.len(), 3);
+        assert_eq!(uut.default.unwrap().port(), Some(9090));
+        assert_eq!(uut.routes[2].rewrite.as_deref(), Some("/healthz"));
+    }
+
+    #[test]
+    fn resolve_prefix() {
+        let uut: RouteTable = toml::from_str(CONFIG).unwrap();
+        let (upstream, path) = uut.resolve("/v1/todos").unwrap();
+        assert_eq!(upstream.port(), Some(8080));
+        assert_eq!(path, "/v1/todos");
+        let (upstream, path) = uut.resolve("/v1/current/Rome").unwrap();
+        assert_eq!(upstream.port(), Some(8081));
+        assert_eq!(path, "/v1/current/Rome");
+        let (upstream, path) = uut.resolve("/v1/todosx").unwrap();
+        assert_eq!(upstream.port(), Some(9090));
+        assert_eq!(path, "/v1/todosx");
+    }
+
+    #[test]
+    fn resolve_rewrite() {
+        let uut: RouteTable = toml::from_str(CONFIG).unwrap();
+        let (upstream, path) = uut.resolve("/healthz/habitica").unwrap();
+        assert_eq!(upstream.port(), Some(8080));
+        assert_eq!(path, "/healthz");
+    }
+
+    #[test]
+    fn resolve_rewrite_to_root() {
+        for rewrite in ["/", ""] {
+            let uut: RouteTable = toml::from_str(&format!(
+                r#"
+[[routes]]
+prefix = "/weather"
+upstream = "http://127.0.0.1:8080/"
+rewrite = "{}"
+"#,
+                rewrite
+            ))
+            .unwrap();
+            assert_eq!(uut.resolve("/weather").unwrap().1, "/");
+            assert_eq!(uut.resolve("/weather/").unwrap().1, "/");
+            assert_eq!(uut.resolve("/weather/current").unwrap().1, "/current");
+        }
+    }
+
+    #[test]
+    fn resolve_longest_prefix() {
+        let uut: RouteTable = toml::from_str(
+            r#"
+[[routes]]
+prefix = "/v1"
+upstream = "http://127.0.0.1:9090/"
+
+[[routes]]
+prefix = "/v1/todos/"
+upstream = "http://127.0.0.1:8080/"
+"#,
+        )
+        .unwrap();
+        assert_eq!(uut.resolve("/v1/todos").unwrap().0.port(), Some(8080));
+        assert_eq!(uut.resolve("/v1/labels").unwrap().0.port(), Some(9090));
+        assert!(uut.resolve("/healthz").is_none());
+    }
+}
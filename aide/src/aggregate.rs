@@ -0,0 +1,133 @@
+use crate::forward::HttpClient;
+use aide_proto::v1::todo::{ModuleError, TodoListResponse};
+use aide_proto::v1::{ModuleInfo, Todo};
+use hyper::header::{HeaderValue, AUTHORIZATION};
+use hyper::{Body, Request, StatusCode};
+use std::time::Duration;
+
+/// How long to wait for a module before reporting it as failed
+const FAN_OUT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The paths of the lists of todos merged from all modules
+pub const AGGREGATED_PATHS: [&str; 2] = ["/v1/todos", "/v1/todos/completed"];
+
+/// Query `GET path` on all the modules concurrently and merge the replies
+///
+/// `query` and `authorization` are forwarded as they are to every module
+pub async fn get_todos(
+    client: &HttpClient,
+    modules: Vec<ModuleInfo>,
+    path: &str,
+    query: Option<&str>,
+    authorization: Option<HeaderValue>,
+) -> TodoListResponse {
+    let handles: Vec<_> = modules
+        .into_iter()
+        .map(|module| {
+            let client = client.clone();
+            let path = path.trim_start_matches('/').to_string();
+            let query = query.map(|q| q.to_string());
+            let authorization = authorization.clone();
+            // kept outside of the task, to report the module even if the task fails
+            let name = module.name.clone();
+            let handle = tokio::spawn(async move {
+                tokio::time::timeout(
+                    FAN_OUT_TIMEOUT,
+                    get_module_todos(&client, &module, &path, query, authorization),
+                )
+                .await
+                .unwrap_or_else(|_| Err("timeout".to_string()))
+            });
+            (name, handle)
+        })
+        .collect();
+    let mut results = Vec::with_capacity(handles.len());
+    for (name, handle) in handles {
+        let result = handle
+            .await
+            .unwrap_or_else(|e| Err(format!("request failed [{}]", e)));
+        results.push((name, result));
+    }
+    merge(results)
+}
+
+async fn get_module_todos(
+    client: &HttpClient,
+    module: &ModuleInfo,
+    path: &str,
+    query: Option<String>,
+    authorization: Option<HeaderValue>,
+) -> Result<Vec<Todo>, String> {
+    let mut url = module.callback.join(path).map_err(|e| e.to_string())?;
+    url.set_query(query.as_deref());
+    let mut req = Request::get(url.as_str())
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+    if let Some(a) = authorization {
+        req.headers_mut().insert(AUTHORIZATION, a);
+    }
+    let res = client.request(req).await.map_err(|e| e.to_string())?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    if status != StatusCode::OK {
+        return Err(format!("{}: {}", status, String::from_utf8_lossy(&body)));
+    }
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+/// Merge the replies of the modules, tagging each todo with the module that provided it
+fn merge(results: Vec<(String, Result<Vec<Todo>, String>)>) -> TodoListResponse {
+    let mut response = TodoListResponse::default();
+    for (module, result) in results {
+        match result {
+            Ok(todos) => response.data.extend(todos.into_iter().map(|mut t| {
+                t.source.get_or_insert_with(|| module.clone());
+                t
+            })),
+            Err(error) => response.errors.push(ModuleError { module, error }),
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_tags_the_source() {
+        let work = vec![Todo::from("report".to_string())];
+        let personal = vec![
+            Todo::from("groceries".to_string()),
+            Todo::from("laundry".to_string()),
+        ];
+        let uut = merge(vec![
+            ("work".to_string(), Ok(work)),
+            ("personal".to_string(), Ok(personal)),
+        ]);
+        assert!(uut.errors.is_empty());
+        assert_eq!(uut.data.len(), 3);
+        assert_eq!(uut.data[0].source.as_deref(), Some("work"));
+        assert_eq!(uut.data[1].source.as_deref(), Some("personal"));
+        assert_eq!(uut.data[2].source.as_deref(), Some("personal"));
+    }
+
+    #[test]
+    fn merge_partial_failure() {
+        let work = vec![Todo::from("report".to_string())];
+        let uut = merge(vec![
+            ("work".to_string(), Ok(work)),
+            ("personal".to_string(), Err("timeout".to_string())),
+        ]);
+        assert_eq!(uut.data.len(), 1);
+        assert_eq!(
+            uut.errors,
+            vec![ModuleError {
+                module: "personal".to_string(),
+                error: "timeout".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use clap::Parser;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+#[derive(Parser, Clone, Debug)]
+#[clap(author, version, about)]
+pub struct Opt {
+    #[clap(name = "host", long = "host", short = 'H', default_value = "127.0.0.1")]
+    /// Set the listening IP address
+    pub host_addr: IpAddr,
+
+    #[clap(short = 'p', long, default_value_t = 9090)]
+    /// Listening TCP port of the hub
+    pub port: u16,
+
+    #[clap(long, default_value_t = 30)]
+    /// How often, in seconds, plugins have to send an heartbeat
+    pub heartbeat_interval: u64,
+
+    #[clap(long, default_value_t = 10)]
+    /// How often, in seconds, the health of the plugins is checked
+    pub health_interval: u64,
+
+    #[clap(long, default_value_t = 3)]
+    /// Number of consecutive failed health checks before a plugin is considered unhealthy
+    pub max_failures: u32,
+
+    #[clap(long, default_value_t = 300)]
+    /// How long, in seconds, an unhealthy plugin is kept before being removed
+    pub eviction_grace: u64,
+
+    #[clap(long, parse(from_os_str))]
+    /// The file where registered plugins are saved [default: $XDG_STATE_HOME/aide/modules.json]
+    pub state_file: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Keep registered plugins in memory only
+    pub no_persist: bool,
+
+    #[clap(long, env = "AIDE_TOKEN", hide_env_values = true)]
+    /// The API token clients and plugins have to send as bearer token
+    pub token: Option<String>,
+}
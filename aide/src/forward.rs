@@ -0,0 +1,75 @@
+pub use aide_common::proxy::HttpClient;
+use aide_proto::v1::{ModuleInfo, ModuleKind};
+use hyper::{Body, Request, Response};
+
+const TODO_PREFIXES: [&str; 3] = ["/v1/todos", "/v1/types", "/v1/labels"];
+const WEATHER_PREFIXES: [&str; 3] = ["/v1/current", "/v1/forecast", "/v1/hourrainforecast"];
+
+/// Return the kind of module serving the path, if any
+pub fn route_kind(path: &str) -> Option<ModuleKind> {
+    let matches = |prefix: &&str| {
+        path.strip_prefix(*prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    if TODO_PREFIXES.iter().any(matches) {
+        Some(ModuleKind::Todo)
+    } else if WEATHER_PREFIXES.iter().any(matches) {
+        Some(ModuleKind::Weather)
+    } else {
+        None
+    }
+}
+
+/// Return the module selected by the `source` query parameter, if any
+///
+/// Todo IDs are unique only within the module that provided them, so requests about
+/// a specific todo have to name its source
+pub fn source_param(query: Option<&str>) -> Option<String> {
+    url::form_urlencoded::parse(query?.as_bytes())
+        .find(|(k, _)| k == "source")
+        .map(|(_, v)| v.into_owned())
+}
+
+/// Forward the request to the module, as it is
+pub async fn forward(
+    client: &HttpClient,
+    module: &ModuleInfo,
+    req: Request<Body>,
+) -> Result<Response<Body>, anyhow::Error> {
+    Ok(aide_common::proxy::forward(client, &module.callback, req).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_kind_todo() {
+        assert_eq!(route_kind("/v1/todos"), Some(ModuleKind::Todo));
+        assert_eq!(route_kind("/v1/types/daily/todos"), Some(ModuleKind::Todo));
+        assert_eq!(route_kind("/v1/labels/work"), Some(ModuleKind::Todo));
+        assert_eq!(route_kind("/v1/todosx"), None);
+    }
+
+    #[test]
+    fn source_from_query() {
+        assert_eq!(source_param(None), None);
+        assert_eq!(source_param(Some("label=work")), None);
+        assert_eq!(
+            source_param(Some("label=work&source=habitica")).as_deref(),
+            Some("habitica")
+        );
+    }
+
+    #[test]
+    fn route_kind_weather() {
+        assert_eq!(route_kind("/v1/current"), Some(ModuleKind::Weather));
+        assert_eq!(route_kind("/v1/forecast/Rome"), Some(ModuleKind::Weather));
+        assert_eq!(
+            route_kind("/v1/hourrainforecast"),
+            Some(ModuleKind::Weather)
+        );
+        assert_eq!(route_kind("/v1/modules"), None);
+        assert_eq!(route_kind("/healthz"), None);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::cli::Opt;
+use crate::forward::HttpClient;
+use crate::registry::Registry;
+use aide_proto::v1::ModuleInfo;
+use hyper::{Body, Request, StatusCode};
+use log::{info, warn};
+use std::time::Duration;
+
+/// Periodically probe the `/healthz` endpoint of all registered modules
+///
+/// Modules failing `max_failures` probes in a row are marked as unhealthy and they are
+/// evicted if they are still unhealthy after the grace period.
+/// This function never returns: it's meant to be spawned
+pub async fn monitor(registry: Registry, client: HttpClient, opt: Opt) {
+    let interval = Duration::from_secs(opt.health_interval);
+    let grace = Duration::from_secs(opt.eviction_grace);
+    loop {
+        tokio::time::sleep(interval).await;
+        let modules = registry.list(None).await;
+        let handles: Vec<_> = modules
+            .into_iter()
+            .map(|module| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let healthy = probe(&client, &module, interval).await;
+                    (module.name, healthy)
+                })
+            })
+            .collect();
+        for handle in handles {
+            if let Ok((name, healthy)) = handle.await {
+                if !healthy {
+                    warn!("Health probe of {} failed", name);
+                }
+                registry
+                    .record_probe(&name, healthy, opt.max_failures)
+                    .await;
+            }
+        }
+        for module in registry.evict(grace).await {
+            info!("Module {} evicted, unhealthy for too long", module.name);
+        }
+    }
+}
+
+pub async fn probe(client: &HttpClient, module: &ModuleInfo, timeout: Duration) -> bool {
+    let url = match module.callback.join("healthz") {
+        Ok(u) => u,
+        Err(_) => return false,
+    };
+    let req = match Request::get(url.as_str()).body(Body::empty()) {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    match tokio::time::timeout(timeout, client.request(req)).await {
+        Ok(Ok(res)) => res.status() == StatusCode::OK,
+        _ => false,
+    }
+}
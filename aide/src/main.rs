@@ -1,3 +1,282 @@
-fn main() {
-    println!("Hello, world!");
+mod aggregate;
+mod cli;
+mod forward;
+mod health;
+mod registry;
+mod store;
+
+use aide_common::{check_token, healthz, http_400, http_404, http_502, http_503, shutdown_signal};
+use aide_proto::v1::{
+    DeregisterModuleResponse, HeartbeatRequest, HeartbeatResponse, ModuleInfo, ModuleKind,
+    ModuleListResponse, RegisterModuleRequest, RegisterModuleResponse, API_VERSION,
+};
+use clap::Parser;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use log::{info, warn};
+use registry::Registry;
+use std::str::FromStr;
+
+#[derive(Clone, Debug)]
+struct State {
+    opt: cli::Opt,
+    registry: Registry,
+    client: forward::HttpClient,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    env_logger::try_init()
+        .unwrap_or_else(|e| eprintln!("Failed to initialize env_logger [{:?}]", e));
+    let opt = cli::Opt::parse();
+    let state_file = if opt.no_persist {
+        None
+    } else {
+        opt.state_file.clone().or_else(store::default_path)
+    };
+    let state = State {
+        opt: opt.clone(),
+        registry: Registry::new(state_file.clone()),
+        client: forward::HttpClient::new(),
+    };
+    if let Some(path) = state_file {
+        store::restore(&state.registry, &state.client, &path).await;
+    }
+    tokio::spawn(health::monitor(
+        state.registry.clone(),
+        state.client.clone(),
+        opt.clone(),
+    ));
+    let service = make_service_fn(|_| {
+        let cloned_state = state.clone();
+        async { Ok::<_, hyper::Error>(service_fn(move |req| aide_svc(req, cloned_state.clone()))) }
+    });
+
+    let socket_addr = std::net::SocketAddr::new(opt.host_addr, opt.port);
+    let server = Server::bind(&socket_addr)
+        .serve(service)
+        .with_graceful_shutdown(shutdown_signal());
+    server.await?;
+    Ok(())
+}
+
+async fn aide_svc(req: Request<Body>, state: State) -> Result<Response<Body>, anyhow::Error> {
+    if req.uri().path() == "/healthz" {
+        return Ok(healthz());
+    }
+    if let Some(response) = check_token(&req, state.opt.token.as_deref()) {
+        return Ok(response);
+    }
+    if let Some(kind) = forward::route_kind(req.uri().path()) {
+        return route(req, kind, state).await;
+    }
+    if req.method() != Method::GET && req.method() != Method::DELETE && req.method() != Method::POST
+    {
+        return Ok(http_404(
+            &"The only methods supported are GET, POST and DELETE",
+        ));
+    }
+    if !req.uri().path().starts_with("/v1") {
+        return Ok(http_404(&"Invalid path"));
+    }
+    if req.uri().path().starts_with("/v1/modules") {
+        return modules(req, state).await;
+    } else if req.uri().path() == "/v1/heartbeat" && req.method() == Method::POST {
+        return heartbeat(req, state).await;
+    }
+    Ok(http_404(&""))
+}
+
+async fn modules(req: Request<Body>, state: State) -> Result<Response<Body>, anyhow::Error> {
+    let path = url_to_pathvec(req.uri());
+    // module names are percent-encoded by the plugins
+    let name = path
+        .get(2)
+        .map(|n| percent_encoding::percent_decode_str(n).decode_utf8_lossy());
+    match (req.method(), path.len()) {
+        (&Method::GET, 2) => list_modules(req, state).await,
+        (&Method::POST, 2) => register_module(req, state).await,
+        (&Method::GET, 3) => {
+            let name = name.unwrap_or_default();
+            if let Some(module) = state.registry.get(&name).await {
+                Ok(Response::builder()
+                    .body(Body::from(serde_json::to_string(&module).unwrap()))
+                    .unwrap())
+            } else {
+                Ok(http_404(&format!("module {} not found", name)))
+            }
+        }
+        (&Method::DELETE, 3) => deregister_module(&name.unwrap_or_default(), state).await,
+        _ => Ok(http_404(&format!(
+            "path not recognized: {}",
+            req.uri().path()
+        ))),
+    }
+}
+
+// GET /v1/modules[?kind=:kind]
+async fn list_modules(req: Request<Body>, state: State) -> Result<Response<Body>, anyhow::Error> {
+    let mut kind = None;
+    if let Some(query) = req.uri().query() {
+        for (k, v) in url::form_urlencoded::parse(query.as_bytes()) {
+            if k == "kind" {
+                match ModuleKind::from_str(&v) {
+                    Ok(k) => kind = Some(k),
+                    Err(_) => return Ok(http_400(&format!("Unknown module kind: {}", v))),
+                }
+            }
+        }
+    }
+    let data = state.registry.list(kind).await;
+    let response = ModuleListResponse { data };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// POST /v1/modules
+// { name: "module_name", kind: "Todo", callback: "http://127.0.0.1:8080/" }
+async fn register_module(
+    req: Request<Body>,
+    state: State,
+) -> Result<Response<Body>, anyhow::Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let request: RegisterModuleRequest = match serde_json::from_slice(&body) {
+        Ok(r) => r,
+        Err(e) => return Ok(http_400(&format!("Invalid registration request: {}", e))),
+    };
+    if request.api_version != API_VERSION {
+        return Ok(http_400(&format!(
+            "Unsupported API version: {}",
+            request.api_version
+        )));
+    }
+    let module = ModuleInfo::from(request);
+    info!(
+        "Registering module {} ({}) at {}",
+        module.name, module.kind, module.callback
+    );
+    state.registry.register(module).await;
+    let response = RegisterModuleResponse {
+        success: true,
+        heartbeat_interval: state.opt.heartbeat_interval,
+    };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// DELETE /v1/modules/:name
+async fn deregister_module(name: &str, state: State) -> Result<Response<Body>, anyhow::Error> {
+    let module = match state.registry.deregister(name).await {
+        Some(m) => m,
+        None => return Ok(http_404(&format!("module {} not found", name))),
+    };
+    info!("Module {} deregistered", name);
+    let response = DeregisterModuleResponse {
+        success: true,
+        module,
+    };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// Forward the request to the first healthy module of the given kind, or to the one
+// selected by the `source` query parameter
+async fn route(
+    req: Request<Body>,
+    kind: ModuleKind,
+    state: State,
+) -> Result<Response<Body>, anyhow::Error> {
+    let mut modules = state.registry.list_healthy(kind).await;
+    if let Some(source) = forward::source_param(req.uri().query()) {
+        modules.retain(|m| m.name == source);
+        if modules.is_empty() {
+            return Ok(http_404(&format!(
+                "No healthy {} module named {}",
+                kind, source
+            )));
+        }
+    }
+    if kind == ModuleKind::Todo
+        && req.method() == Method::GET
+        && aggregate::AGGREGATED_PATHS.contains(&req.uri().path().trim_end_matches('/'))
+        && !modules.is_empty()
+    {
+        return todos(req, modules, state).await;
+    }
+    let module = match modules.first() {
+        Some(m) => m,
+        None => return Ok(http_503(&format!("No healthy {} module available", kind))),
+    };
+    match forward::forward(&state.client, module, req).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Failed to forward the request to {} [{}]", module.name, e);
+            Ok(http_502(&format!("Module {} not reachable", module.name)))
+        }
+    }
+}
+
+// GET /v1/todos and GET /v1/todos/completed, merging the todos of all todo modules
+async fn todos(
+    req: Request<Body>,
+    modules: Vec<ModuleInfo>,
+    state: State,
+) -> Result<Response<Body>, anyhow::Error> {
+    let module_count = modules.len();
+    let authorization = req.headers().get(hyper::header::AUTHORIZATION).cloned();
+    let path = req.uri().path().trim_end_matches('/');
+    let mut response = aggregate::get_todos(
+        &state.client,
+        modules,
+        path,
+        req.uri().query(),
+        authorization,
+    )
+    .await;
+    if path == "/v1/todos/completed" {
+        response
+            .data
+            .sort_by_key(|t| std::cmp::Reverse(t.completed_at));
+    }
+    for e in response.errors.iter() {
+        warn!("Failed to get todos from {} [{}]", e.module, e.error);
+    }
+    let status = if response.errors.len() == module_count {
+        StatusCode::BAD_GATEWAY
+    } else {
+        StatusCode::OK
+    };
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// POST /v1/heartbeat
+// { name: "module_name" }
+async fn heartbeat(req: Request<Body>, state: State) -> Result<Response<Body>, anyhow::Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let request: HeartbeatRequest = match serde_json::from_slice(&body) {
+        Ok(r) => r,
+        Err(e) => return Ok(http_400(&format!("Invalid heartbeat: {}", e))),
+    };
+    let registered = state.registry.seen(&request.name).await;
+    if !registered {
+        info!("Heartbeat from unknown module {}", request.name);
+    }
+    let response = HeartbeatResponse { registered };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+fn url_to_pathvec(uri: &Uri) -> Vec<&str> {
+    uri.path()
+        .split('/')
+        .skip_while(|x| x.is_empty())
+        .filter(|x| !x.is_empty())
+        .collect()
 }
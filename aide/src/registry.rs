@@ -0,0 +1,297 @@
+use aide_proto::v1::{ModuleHealth, ModuleInfo, ModuleKind};
+use log::warn;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+
+#[derive(Debug, Clone)]
+struct Entry {
+    info: ModuleInfo,
+    /// Consecutive failed health probes
+    failures: u32,
+    unhealthy_since: Option<Instant>,
+}
+
+impl From<ModuleInfo> for Entry {
+    fn from(info: ModuleInfo) -> Self {
+        Entry {
+            info,
+            failures: 0,
+            unhealthy_since: None,
+        }
+    }
+}
+
+/// The in-memory list of the plugins registered to the hub
+///
+/// Modules are indexed by name: a plugin registering again with the same
+/// name replaces the previous entry.
+/// If a store file is provided, the list of modules is saved there every time a module is
+/// added or removed.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    modules: Arc<RwLock<HashMap<String, Entry>>>,
+    store: Option<PathBuf>,
+    store_lock: Arc<Mutex<()>>,
+}
+
+impl Registry {
+    pub fn new(store: Option<PathBuf>) -> Self {
+        Registry {
+            store,
+            ..Registry::default()
+        }
+    }
+
+    /// Add or replace a module, returning the previous entry, if any
+    pub async fn register(&self, module: ModuleInfo) -> Option<ModuleInfo> {
+        let result = self.restore(module).await;
+        self.persist().await;
+        result
+    }
+
+    /// Add a module like `register`, without saving the store file
+    ///
+    /// Meant for the modules restored from the store file, saved once all are restored
+    pub async fn restore(&self, mut module: ModuleInfo) -> Option<ModuleInfo> {
+        module.health = ModuleHealth::Healthy;
+        module.last_seen = Some(now());
+        let mut unlocked_modules = self.modules.write().await;
+        unlocked_modules
+            .insert(module.name.clone(), Entry::from(module))
+            .map(|e| e.info)
+    }
+
+    /// Remove a module, returning it if it was registered
+    pub async fn deregister(&self, name: &str) -> Option<ModuleInfo> {
+        let mut unlocked_modules = self.modules.write().await;
+        let result = unlocked_modules.remove(name).map(|e| e.info);
+        drop(unlocked_modules);
+        if result.is_some() {
+            self.persist().await;
+        }
+        result
+    }
+
+    /// Save the list of modules in the store file, if any
+    pub async fn persist(&self) {
+        let path = match &self.store {
+            Some(p) => p,
+            None => return,
+        };
+        let _guard = self.store_lock.lock().await;
+        let modules = self.list(None).await;
+        if let Err(e) = crate::store::save(path, modules).await {
+            warn!("Failed to save modules in {} [{}]", path.display(), e);
+        }
+    }
+
+    pub async fn get(&self, name: &str) -> Option<ModuleInfo> {
+        let unlocked_modules = self.modules.read().await;
+        unlocked_modules.get(name).map(|e| e.info.clone())
+    }
+
+    /// List the registered modules, sorted by name, optionally only the ones of a specific kind
+    pub async fn list(&self, kind: Option<ModuleKind>) -> Vec<ModuleInfo> {
+        let unlocked_modules = self.modules.read().await;
+        let mut result: Vec<ModuleInfo> = unlocked_modules
+            .values()
+            .map(|e| &e.info)
+            .filter(|m| kind.is_none() || kind == Some(m.kind))
+            .cloned()
+            .collect();
+        drop(unlocked_modules);
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        result
+    }
+
+    /// List the healthy modules of a specific kind, sorted by name
+    pub async fn list_healthy(&self, kind: ModuleKind) -> Vec<ModuleInfo> {
+        let mut result = self.list(Some(kind)).await;
+        result.retain(|m| m.health == ModuleHealth::Healthy);
+        result
+    }
+
+    /// Record that the module is alive (i.e. it sent an heartbeat), returning false if the
+    /// module is not registered
+    pub async fn seen(&self, name: &str) -> bool {
+        let mut unlocked_modules = self.modules.write().await;
+        match unlocked_modules.get_mut(name) {
+            Some(entry) => {
+                entry.info.last_seen = Some(now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record the outcome of an health probe
+    ///
+    /// After `max_failures` consecutive failures, the module is marked as unhealthy.
+    /// A successful probe marks the module as healthy again
+    pub async fn record_probe(&self, name: &str, success: bool, max_failures: u32) {
+        let mut unlocked_modules = self.modules.write().await;
+        let entry = match unlocked_modules.get_mut(name) {
+            Some(e) => e,
+            None => return,
+        };
+        if success {
+            entry.failures = 0;
+            entry.unhealthy_since = None;
+            entry.info.health = ModuleHealth::Healthy;
+            entry.info.last_seen = Some(now());
+        } else {
+            entry.failures = entry.failures.saturating_add(1);
+            if entry.failures >= max_failures && entry.unhealthy_since.is_none() {
+                entry.unhealthy_since = Some(Instant::now());
+                entry.info.health = ModuleHealth::Unhealthy;
+            }
+        }
+    }
+
+    /// Remove the modules that have been unhealthy for longer than `grace`, returning them
+    pub async fn evict(&self, grace: Duration) -> Vec<ModuleInfo> {
+        let mut unlocked_modules = self.modules.write().await;
+        let expired: Vec<String> = unlocked_modules
+            .values()
+            .filter(|e| e.unhealthy_since.is_some_and(|t| t.elapsed() >= grace))
+            .map(|e| e.info.name.clone())
+            .collect();
+        let result: Vec<ModuleInfo> = expired
+            .iter()
+            .filter_map(|name| unlocked_modules.remove(name))
+            .map(|e| e.info)
+            .collect();
+        drop(unlocked_modules);
+        if !result.is_empty() {
+            self.persist().await;
+        }
+        result
+    }
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str, kind: ModuleKind, port: u16) -> ModuleInfo {
+        ModuleInfo {
+            name: name.to_string(),
+            kind,
+            callback: url::Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap(),
+            api_version: aide_proto::v1::API_VERSION.to_string(),
+            health: ModuleHealth::Healthy,
+            last_seen: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn register_and_deregister() {
+        let uut = Registry::default();
+        assert!(uut
+            .register(module("habitica", ModuleKind::Todo, 8080))
+            .await
+            .is_none());
+        let got = uut.get("habitica").await.unwrap();
+        assert_eq!(got.callback.port(), Some(8080));
+        assert!(got.last_seen.is_some());
+        assert!(uut.deregister("habitica").await.is_some());
+        assert!(uut.get("habitica").await.is_none());
+        assert!(uut.deregister("habitica").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn register_twice_replaces() {
+        let uut = Registry::default();
+        uut.register(module("habitica", ModuleKind::Todo, 8080))
+            .await;
+        let previous = uut
+            .register(module("habitica", ModuleKind::Todo, 8081))
+            .await;
+        assert_eq!(previous.unwrap().callback.port(), Some(8080));
+        assert_eq!(uut.list(None).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn list_by_kind() {
+        let uut = Registry::default();
+        uut.register(module("weatherapi", ModuleKind::Weather, 8081))
+            .await;
+        uut.register(module("habitica", ModuleKind::Todo, 8080))
+            .await;
+        let all = uut.list(None).await;
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].name, "habitica");
+        assert_eq!(all[1].name, "weatherapi");
+        let todos = uut.list(Some(ModuleKind::Todo)).await;
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].name, "habitica");
+        assert!(uut.list(Some(ModuleKind::Event)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unhealthy_after_max_failures() {
+        let uut = Registry::default();
+        uut.register(module("habitica", ModuleKind::Todo, 8080))
+            .await;
+        uut.record_probe("habitica", false, 2).await;
+        assert_eq!(uut.list_healthy(ModuleKind::Todo).await.len(), 1);
+        uut.record_probe("habitica", false, 2).await;
+        assert!(uut.list_healthy(ModuleKind::Todo).await.is_empty());
+        assert_eq!(
+            uut.get("habitica").await.unwrap().health,
+            ModuleHealth::Unhealthy
+        );
+        uut.record_probe("habitica", true, 2).await;
+        assert_eq!(uut.list_healthy(ModuleKind::Todo).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn persist_on_change() {
+        let dir = std::env::temp_dir().join(format!("aide-registry-test-{}", std::process::id()));
+        let path = dir.join("modules.json");
+        let uut = Registry::new(Some(path.clone()));
+        uut.register(module("habitica", ModuleKind::Todo, 8080))
+            .await;
+        uut.register(module("weatherapi", ModuleKind::Weather, 8081))
+            .await;
+        assert_eq!(crate::store::load(&path).await.unwrap().len(), 2);
+        uut.deregister("habitica").await;
+        let got = crate::store::load(&path).await.unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].name, "weatherapi");
+        // restored modules are saved only when asked
+        uut.restore(module("habitica", ModuleKind::Todo, 8080))
+            .await;
+        assert_eq!(crate::store::load(&path).await.unwrap().len(), 1);
+        uut.persist().await;
+        assert_eq!(crate::store::load(&path).await.unwrap().len(), 2);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn evict_unhealthy() {
+        let uut = Registry::default();
+        uut.register(module("habitica", ModuleKind::Todo, 8080))
+            .await;
+        uut.register(module("weatherapi", ModuleKind::Weather, 8081))
+            .await;
+        uut.record_probe("habitica", false, 1).await;
+        assert!(uut.evict(Duration::from_secs(3600)).await.is_empty());
+        let evicted = uut.evict(Duration::ZERO).await;
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].name, "habitica");
+        assert!(uut.get("habitica").await.is_none());
+        assert!(uut.get("weatherapi").await.is_some());
+    }
+}
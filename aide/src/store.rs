@@ -0,0 +1,118 @@
+use crate::forward::HttpClient;
+use crate::health::probe;
+use crate::registry::Registry;
+use aide_proto::v1::{ModuleInfo, ModuleListResponse};
+use log::{info, warn};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const STATE_FILE_NAME: &str = "modules.json";
+const RESTORE_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default location of the registry file: `$XDG_STATE_HOME/aide/modules.json`, falling
+/// back to `$HOME/.local/state/aide/modules.json`
+pub fn default_path() -> Option<PathBuf> {
+    path_from_env(std::env::var_os("XDG_STATE_HOME"), std::env::var_os("HOME"))
+}
+
+fn path_from_env(state_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let state_home = match state_home {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(home?).join(".local").join("state"),
+    };
+    Some(state_home.join("aide").join(STATE_FILE_NAME))
+}
+
+pub async fn load(path: &Path) -> Result<Vec<ModuleInfo>, anyhow::Error> {
+    let content = tokio::fs::read(path).await?;
+    let modules: ModuleListResponse = serde_json::from_slice(&content)?;
+    Ok(modules.data)
+}
+
+/// Write the modules in the file, atomically
+pub async fn save(path: &Path, modules: Vec<ModuleInfo>) -> Result<(), anyhow::Error> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let content = serde_json::to_vec_pretty(&ModuleListResponse { data: modules })?;
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, content).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Load the modules saved in the file, adding to the registry only the ones that are still
+/// alive
+pub async fn restore(registry: &Registry, client: &HttpClient, path: &Path) {
+    let modules = match load(path).await {
+        Ok(m) => m,
+        Err(e) => {
+            if path.exists() {
+                warn!("Failed to load modules from {} [{}]", path.display(), e);
+            }
+            return;
+        }
+    };
+    // probe all the modules at once, so dead modules don't add up to the startup time
+    let handles: Vec<_> = modules
+        .into_iter()
+        .map(|module| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                let alive = probe(&client, &module, RESTORE_PROBE_TIMEOUT).await;
+                (module, alive)
+            })
+        })
+        .collect();
+    for handle in handles {
+        if let Ok((module, alive)) = handle.await {
+            if alive {
+                info!("Module {} restored", module.name);
+                registry.restore(module).await;
+            } else {
+                info!("Module {} not reachable, not restored", module.name);
+            }
+        }
+    }
+    // saved once at the end, so that a crash while restoring leaves the file as it was;
+    // only the restored modules are kept, dropping the unreachable ones
+    registry.persist().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aide_proto::v1::{ModuleHealth, ModuleKind, API_VERSION};
+
+    #[test]
+    fn xdg_state_home() {
+        assert_eq!(
+            path_from_env(Some("/var/state".into()), Some("/home/me".into())),
+            Some(PathBuf::from("/var/state/aide/modules.json"))
+        );
+        assert_eq!(
+            path_from_env(Some("".into()), Some("/home/me".into())),
+            Some(PathBuf::from("/home/me/.local/state/aide/modules.json"))
+        );
+        assert_eq!(path_from_env(None, None), None);
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("aide-store-test-{}", std::process::id()));
+        let path = dir.join(STATE_FILE_NAME);
+        let modules = vec![ModuleInfo {
+            name: "habitica".to_string(),
+            kind: ModuleKind::Todo,
+            callback: url::Url::parse("http://127.0.0.1:8080/").unwrap(),
+            api_version: API_VERSION.to_string(),
+            health: ModuleHealth::Healthy,
+            last_seen: Some(1_650_000_000),
+        }];
+        save(&path, modules.clone()).await.unwrap();
+        let got = load(&path).await.unwrap();
+        assert_eq!(got, modules);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -2,6 +2,34 @@ use clap::Parser;
 
 #[derive(Parser, Clone, Debug)]
 pub struct Opt {
+    #[clap(long, parse(try_from_str = parse_hour))]
+    /// The hour a new Habitica day starts [default: the one in the user's preferences]
+    pub day_start: Option<u32>,
+
+    #[clap(long, arg_enum, default_value_t = Classification::Recurrence)]
+    /// How Habitica dailies are classified as daily or weekly todos
+    pub classification: Classification,
+
+    #[clap(long, default_value_t = 300)]
+    /// How often, in seconds, the labels are reloaded from Habitica; 0 to disable it
+    pub tag_refresh_interval: u64,
+
     #[clap(flatten)]
     pub common_opt: aide_common::CommonOpt,
 }
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum Classification {
+    /// From their frequency and the days they repeat on
+    #[default]
+    Recurrence,
+    /// From the "daily" and "weekly" labels; unlabeled dailies are not listed
+    Tags,
+}
+
+fn parse_hour(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(hour) if hour < 24 => Ok(hour),
+        _ => Err(format!("{} is not an hour between 0 and 23", s)),
+    }
+}
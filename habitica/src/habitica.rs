@@ -1,4 +1,4 @@
-use aide_proto::v1::todo::{CheckListItem, Todo};
+use aide_proto::v1::todo::{CheckListItem, DueDate, HabitCounters, Todo};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug)]
@@ -8,6 +8,7 @@ pub struct RespGeneric {
 
 #[derive(Deserialize, Debug)]
 pub struct RespTask {
+    #[allow(dead_code)]
     pub success: bool,
     pub data: Vec<Task>,
 }
@@ -28,10 +29,67 @@ pub struct Task {
     pub checklist: Option<Vec<Checklist>>,
     #[serde(rename(deserialize = "completed"))]
     pub completed: Option<bool>,
+    /// The due date of todos, as an ISO-8601 date-time
+    #[serde(default, rename(deserialize = "date"))]
+    pub date: Option<String>,
+    /// Habits only
+    #[serde(default, rename(deserialize = "up"))]
+    pub up: Option<bool>,
+    /// Habits only
+    #[serde(default, rename(deserialize = "down"))]
+    pub down: Option<bool>,
+    /// Habits only
+    #[serde(default, rename(deserialize = "counterUp"))]
+    pub counter_up: Option<u32>,
+    /// Habits only
+    #[serde(default, rename(deserialize = "counterDown"))]
+    pub counter_down: Option<u32>,
+    /// The cost of rewards
+    #[serde(default, rename(deserialize = "value"))]
+    pub value: Option<f64>,
+    /// Completed todos only
+    #[serde(default, rename(deserialize = "dateCompleted"))]
+    pub date_completed: Option<String>,
+    /// Dailies only
+    #[serde(default, rename(deserialize = "frequency"))]
+    pub frequency: Option<String>,
+    /// Dailies only
+    #[serde(default, rename(deserialize = "everyX"))]
+    pub every: Option<u32>,
+    /// Dailies only
+    #[serde(default, rename(deserialize = "repeat"))]
+    pub repeat: Option<Repeat>,
+}
+
+/// The due date to send to Habitica: a day is sent as its midnight in the user's
+/// `timezone`, as Habitica would take it as midnight UTC
+pub fn habitica_date(due: DueDate, timezone: &chrono::FixedOffset) -> DueDate {
+    match due {
+        DueDate::Date(d) => d
+            .and_time(chrono::NaiveTime::MIN)
+            .and_local_timezone(*timezone)
+            .single()
+            .map_or(due, DueDate::DateTime),
+        DueDate::DateTime(_) => due,
+    }
 }
 
-impl From<&Task> for Todo {
-    fn from(t: &Task) -> Self {
+impl Task {
+    /// The due date in the user's `timezone`: only the day when it's at midnight, as
+    /// for the dates set in Habitica, otherwise the date and time
+    fn due_date(&self, timezone: &chrono::FixedOffset) -> Option<DueDate> {
+        let date = chrono::DateTime::parse_from_rfc3339(self.date.as_deref()?)
+            .ok()?
+            .with_timezone(timezone);
+        if date.time() == chrono::NaiveTime::MIN {
+            Some(DueDate::from(date.date_naive()))
+        } else {
+            Some(DueDate::DateTime(date))
+        }
+    }
+    /// Convert the task in a todo, with the due date in the user's `timezone`
+    pub fn to_todo(&self, timezone: &chrono::FixedOffset) -> Todo {
+        let t = self;
         let name = t.description.clone();
         let descr = Some(t.notes.clone());
         let tags = t.tags.clone();
@@ -39,29 +97,61 @@ impl From<&Task> for Todo {
             None => Vec::new(),
             Some(v) => v.iter().map(|cl| cl.into()).collect(),
         };
-        let due_date = None;
+        let due_date = t.due_date(timezone);
         let done = t.completed.unwrap_or(false);
+        let todo_type = match t.task_type {
+            TaskTypes::Daily => match (&t.frequency, &t.repeat) {
+                (Some(frequency), Some(repeat)) => {
+                    daily_todo_type(frequency, t.every.unwrap_or(1), repeat)
+                }
+                _ => aide_proto::v1::todo::TodoTypes::Daily,
+            },
+            TaskTypes::Habit => aide_proto::v1::todo::TodoTypes::Habit,
+            TaskTypes::Reward => aide_proto::v1::todo::TodoTypes::Reward,
+            TaskTypes::Todo => aide_proto::v1::todo::TodoTypes::Task,
+        };
+        let habit = match t.task_type {
+            TaskTypes::Habit => Some(HabitCounters {
+                up: t.up.unwrap_or(true),
+                down: t.down.unwrap_or(true),
+                counter_up: t.counter_up.unwrap_or_default(),
+                counter_down: t.counter_down.unwrap_or_default(),
+            }),
+            _ => None,
+        };
+        let cost = match t.task_type {
+            TaskTypes::Reward => t.value,
+            _ => None,
+        };
         Todo {
+            id: Some(t.id.clone()),
             name,
             descr,
             tags,
-            todo_type: aide_proto::v1::todo::TodoTypes::Task,
+            todo_type,
             checklist,
             due_date,
             done,
+            source: None,
+            habit,
+            cost,
+            completed_at: t
+                .date_completed
+                .as_deref()
+                .and_then(|d| chrono::DateTime::parse_from_rfc3339(d).ok()),
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum TaskTypes {
-    #[serde(rename(deserialize = "habit"))]
+    #[serde(rename = "habit")]
     Habit,
-    #[serde(rename(deserialize = "todo"))]
+    #[serde(rename = "todo")]
     Todo,
-    #[serde(rename(deserialize = "reward"))]
+    #[serde(rename = "reward")]
     Reward,
-    #[serde(rename(deserialize = "daily"))]
+    #[serde(rename = "daily")]
     Daily,
 }
 
@@ -69,13 +159,13 @@ pub enum TaskTypes {
 pub struct Checklist {
     completed: bool,
     text: String,
-    #[allow(dead_code)]
     id: String,
 }
 
 impl From<&Checklist> for CheckListItem {
     fn from(cl: &Checklist) -> Self {
         CheckListItem {
+            id: Some(cl.id.clone()),
             name: cl.text.clone(),
             done: cl.completed,
         }
@@ -84,6 +174,7 @@ impl From<&Checklist> for CheckListItem {
 
 #[derive(Deserialize, Debug)]
 pub struct RespDaily {
+    #[allow(dead_code)]
     pub success: bool,
     pub data: Vec<Daily>,
 }
@@ -94,6 +185,7 @@ pub struct Daily {
     pub id: String,
     #[serde(rename(deserialize = "text"))]
     pub description: String,
+    #[allow(dead_code)]
     #[serde(rename(deserialize = "type"))]
     pub task_type: TaskTypes,
     #[serde(rename(deserialize = "notes"))]
@@ -108,38 +200,147 @@ pub struct Daily {
     pub repeat: Repeat,
     #[serde(rename(deserialize = "frequency"))]
     pub frequency: String,
-    #[serde(rename(deserialize = "nextDue"))]
+    /// The next days the daily is due, as computed by Habitica
+    #[serde(default, rename(deserialize = "nextDue"))]
     pub next_due: Vec<String>,
     #[serde(rename(deserialize = "everyX"))]
     pub every: u32,
+    #[serde(default, rename(deserialize = "startDate"))]
+    pub start_date: Option<String>,
+    /// Monthly dailies only
+    #[serde(default, rename(deserialize = "daysOfMonth"))]
+    pub days_of_month: Vec<u32>,
+    /// Monthly dailies only, starting from 0
+    #[serde(default, rename(deserialize = "weeksOfMonth"))]
+    pub weeks_of_month: Vec<u32>,
 }
 
-impl Daily {
-    pub fn is_due(&self) -> bool {
-        use chrono::prelude::*;
-        let date = Local::now().naive_local() - chrono::Duration::hours(4);
-        self.is_due_today(&date.date())
+/// Classify a daily from its recurrence
+///
+/// Dailies that can be due more than once a week are daily todos, the others are
+/// weekly todos
+pub fn daily_todo_type(
+    frequency: &str,
+    every: u32,
+    repeat: &Repeat,
+) -> aide_proto::v1::todo::TodoTypes {
+    use aide_proto::v1::todo::TodoTypes;
+    match frequency {
+        "daily" if every < 7 => TodoTypes::Daily,
+        "weekly" if every == 1 && repeat.days() > 1 => TodoTypes::Daily,
+        _ => TodoTypes::Weekly,
+    }
+}
+
+/// Parse the dates Habitica uses for dailies, as the day they fall in `timezone`
+///
+/// `nextDue` can contain dates in the JavaScript format, like
+/// `Wed Dec 09 2020 00:00:00 GMT+0100 (Central European Standard Time)`, which are
+/// already in the user's timezone
+fn parse_daily_date(date: &str, timezone: &chrono::FixedOffset) -> Option<chrono::NaiveDate> {
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Some(date.with_timezone(timezone).date_naive());
     }
-    fn is_due_today(&self, today: &chrono::NaiveDate) -> bool {
+    let date = date.split(" (").next().unwrap_or_default();
+    chrono::DateTime::parse_from_str(date, "%a %b %d %Y %H:%M:%S GMT%z")
+        .ok()
+        .map(|d| d.date_naive())
+}
+
+impl Daily {
+    /// `today` is the current Habitica day, as returned by [`DayStart::today`]
+    ///
+    /// Weekly todos, dailies repeated only on sunday, are due all week long, until
+    /// their checklist is done
+    pub fn is_due_today(&self, today: &chrono::NaiveDate, timezone: &chrono::FixedOffset) -> bool {
         if let Some(done) = self.completed {
             if done {
                 return false;
             }
         }
+        if self.repeats_on(today, timezone) {
+            true
+        } else if self.frequency.as_str() == "weekly"
+            && self.repeat.is_only_sunday()
+            && self.in_repeat_week(today, timezone)
+        {
+            !self.is_checklist_done()
+        } else {
+            false
+        }
+    }
+    /// The day the recurrence starts from, `day` if the daily has no start date
+    fn start_day(
+        &self,
+        day: &chrono::NaiveDate,
+        timezone: &chrono::FixedOffset,
+    ) -> chrono::NaiveDate {
+        self.start_date
+            .as_deref()
+            .and_then(|d| parse_daily_date(d, timezone))
+            .unwrap_or(*day)
+    }
+    /// Whether `day` is in a week a weekly daily repeats in, as `everyX` can skip weeks
+    fn in_repeat_week(&self, day: &chrono::NaiveDate, timezone: &chrono::FixedOffset) -> bool {
+        use chrono::prelude::*;
+        let start = self.start_day(day, timezone);
+        if self.every == 0 || *day < start {
+            return false;
+        }
+        // weeks start on sunday
+        let week_start =
+            |d: &NaiveDate| *d - chrono::Duration::days(d.weekday().num_days_from_sunday() as i64);
+        let weeks = (week_start(day) - week_start(&start)).num_days() / 7;
+        weeks % self.every as i64 == 0
+    }
+    /// Whether the recurrence of the daily falls on `day`, as in Habitica's `shouldDo`
+    fn repeats_on(&self, day: &chrono::NaiveDate, timezone: &chrono::FixedOffset) -> bool {
+        use chrono::prelude::*;
+        if self.every == 0 {
+            return false;
+        }
+        let start = self.start_day(day, timezone);
+        if *day < start {
+            return false;
+        }
+        let every = self.every as i64;
         match self.frequency.as_str() {
-            "daily" => true,
-            "weekly" => {
-                if self.repeat.is_today_on(today) {
-                    true
-                } else if self.repeat.is_only_sunday() {
-                    !self.is_checklist_done()
-                } else {
-                    false
-                }
+            "daily" => (*day - start).num_days() % every == 0,
+            "weekly" => self.in_repeat_week(day, timezone) && self.repeat.is_today_on(day),
+            "monthly" => {
+                let months = (day.year() - start.year()) as i64 * 12 + day.month() as i64
+                    - start.month() as i64;
+                let week_of_month = (day.day() - 1) / 7;
+                months % every == 0
+                    && (self.days_of_month.contains(&day.day())
+                        || (self.weeks_of_month.contains(&week_of_month)
+                            && self.repeat.is_today_on(day)))
+            }
+            "yearly" => {
+                let years = (day.year() - start.year()) as i64;
+                years % every == 0 && day.month() == start.month() && day.day() == start.day()
             }
             _ => true,
         }
     }
+    /// The first day after `today` the daily is due
+    fn next_due_date(
+        &self,
+        today: &chrono::NaiveDate,
+        timezone: &chrono::FixedOffset,
+    ) -> Option<chrono::NaiveDate> {
+        let next_due = self
+            .next_due
+            .iter()
+            .filter_map(|d| parse_daily_date(d, timezone))
+            .find(|d| d > today);
+        // Habitica could omit nextDue, so look for it in the next few years
+        next_due.or_else(|| {
+            (1..=366 * 4)
+                .map(|days| *today + chrono::Duration::days(days))
+                .find(|d| self.repeats_on(d, timezone))
+        })
+    }
     fn is_checklist_done(&self) -> bool {
         if let Some(cl) = self.checklist.clone() {
             for item in cl.iter() {
@@ -150,10 +351,9 @@ impl Daily {
         }
         true
     }
-}
-
-impl From<&Daily> for Todo {
-    fn from(t: &Daily) -> Self {
+    /// Convert the daily in a todo, due on the current Habitica day `today`
+    pub fn to_todo(&self, today: &chrono::NaiveDate, timezone: &chrono::FixedOffset) -> Todo {
+        let t = self;
         let name = t.description.clone();
         let descr = Some(t.notes.clone());
         let tags = t.tags.clone();
@@ -161,33 +361,29 @@ impl From<&Daily> for Todo {
             None => Vec::new(),
             Some(v) => v.iter().map(|cl| cl.into()).collect(),
         };
-        use chrono::prelude::*;
-        let date = Local::now().naive_local() - chrono::Duration::hours(4);
-        let due_date = if t.is_due() && t.repeat.is_today_on(&date.date()) {
-            Some(format!("{}", date.date().format("%Y-%m-%d")))
-        } else if t.is_due() && t.frequency.as_str() == "weekly" {
-            let days_to_sunday = 7i64 - date.weekday().num_days_from_sunday() as i64;
-            let next_sunday = date + chrono::Duration::days(days_to_sunday);
-            Some(format!("{}", next_sunday.date().format("%Y-%m-%d")))
-        } else {
+        let due_date = if !t.is_due_today(today, timezone) {
             None
+        } else if t.repeats_on(today, timezone) {
+            Some(DueDate::from(*today))
+        } else {
+            t.next_due_date(today, timezone).map(DueDate::from)
         };
         let done = t.completed.unwrap_or(false);
-        // Assigning TodoTypes Daily, becuase at this time it would be too complicated to know
-        // if it's Daily or Weekly
         Todo {
+            id: Some(t.id.clone()),
             name,
             descr,
             tags,
-            todo_type: aide_proto::v1::todo::TodoTypes::Daily,
+            todo_type: daily_todo_type(&t.frequency, t.every, &t.repeat),
             checklist,
             due_date,
             done,
+            ..Todo::default()
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Repeat {
     pub su: bool,
     pub s: bool,
@@ -199,6 +395,35 @@ pub struct Repeat {
 }
 
 impl Repeat {
+    fn all_week() -> Self {
+        Repeat {
+            su: true,
+            s: true,
+            f: true,
+            th: true,
+            w: true,
+            t: true,
+            m: true,
+        }
+    }
+    fn only_sunday() -> Self {
+        Repeat {
+            su: true,
+            s: false,
+            f: false,
+            th: false,
+            w: false,
+            t: false,
+            m: false,
+        }
+    }
+    /// The number of days of the week the daily repeats on
+    fn days(&self) -> usize {
+        [self.su, self.s, self.f, self.th, self.w, self.t, self.m]
+            .iter()
+            .filter(|d| **d)
+            .count()
+    }
     fn _is_all_week(&self) -> bool {
         self.su && self.s && self.f && self.th && self.w && self.t && self.m
     }
@@ -220,6 +445,7 @@ impl Repeat {
 }
 #[derive(Deserialize, Debug)]
 pub struct RespTags {
+    #[allow(dead_code)]
     pub success: bool,
     pub data: Vec<Tag>,
 }
@@ -232,15 +458,242 @@ pub struct Tag {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateTagBody {
-    pub name: String
+    pub name: String,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct RespCreateTag {
+    #[allow(dead_code)]
     pub success: bool,
     pub data: Tag,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct RespUser {
+    #[allow(dead_code)]
+    pub success: bool,
+    pub data: User,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct User {
+    pub preferences: Preferences,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Preferences {
+    #[serde(rename(deserialize = "dayStart"))]
+    pub day_start: u32,
+    #[serde(rename(deserialize = "timezoneOffset"))]
+    pub timezone_offset: i32,
+}
+
+/// The time a new day starts for the Habitica user, when dailies are reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DayStart {
+    /// The hour of the day, in the user's timezone
+    pub hour: u32,
+    /// The minutes to add to the user's time to get UTC, as in the user's preferences
+    pub timezone_offset: i32,
+}
+
+impl DayStart {
+    /// The day start at `hour`, in the timezone of this machine
+    pub fn local(hour: u32) -> Self {
+        use chrono::prelude::*;
+        DayStart {
+            hour,
+            timezone_offset: -Local::now().offset().local_minus_utc() / 60,
+        }
+    }
+    /// The current Habitica day
+    pub fn today(&self) -> chrono::NaiveDate {
+        self.day_at(chrono::Utc::now())
+    }
+    /// The timezone of the user
+    pub fn timezone(&self) -> chrono::FixedOffset {
+        chrono::FixedOffset::west_opt(self.timezone_offset * 60)
+            .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap())
+    }
+    fn day_at(&self, now: chrono::DateTime<chrono::Utc>) -> chrono::NaiveDate {
+        let user_time = now.naive_utc()
+            - chrono::Duration::minutes(self.timezone_offset as i64)
+            - chrono::Duration::hours(self.hour as i64);
+        user_time.date()
+    }
+}
+
+impl From<&Preferences> for DayStart {
+    fn from(p: &Preferences) -> Self {
+        DayStart {
+            hour: p.day_start,
+            timezone_offset: p.timezone_offset,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CreateChecklistItem {
+    pub text: String,
+    pub completed: bool,
+}
+
+/// The body of `POST /tasks/user`
+///
+/// Daily todos are dailys repeated every day, while weekly todos are dailys repeated
+/// only on sunday
+#[derive(Serialize, Debug)]
+pub struct CreateTaskBody {
+    pub text: String,
+    #[serde(rename = "type")]
+    pub task_type: TaskTypes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    pub tags: Vec<String>,
+    pub checklist: Vec<CreateChecklistItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<DueDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<Repeat>,
+    /// The cost of rewards
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+}
+
+impl CreateTaskBody {
+    /// Build the body from a todo, where `tag_ids` are the IDs of the todo's tags and
+    /// `timezone` is the user's one
+    pub fn new(todo: &Todo, tag_ids: Vec<String>, timezone: &chrono::FixedOffset) -> Self {
+        use aide_proto::v1::todo::TodoTypes;
+        let date = todo.due_date.map(|d| habitica_date(d, timezone));
+        let (task_type, frequency, repeat, date) = match todo.todo_type {
+            TodoTypes::Task => (TaskTypes::Todo, None, None, date),
+            TodoTypes::Daily => (
+                TaskTypes::Daily,
+                Some("daily".to_string()),
+                Some(Repeat::all_week()),
+                None,
+            ),
+            TodoTypes::Weekly => (
+                TaskTypes::Daily,
+                Some("weekly".to_string()),
+                Some(Repeat::only_sunday()),
+                None,
+            ),
+            TodoTypes::Habit => (TaskTypes::Habit, None, None, None),
+            TodoTypes::Reward => (TaskTypes::Reward, None, None, None),
+        };
+        CreateTaskBody {
+            text: todo.name.clone(),
+            task_type,
+            notes: todo.descr.clone(),
+            tags: tag_ids,
+            checklist: todo
+                .checklist
+                .iter()
+                .map(|c| CreateChecklistItem {
+                    text: c.name.clone(),
+                    completed: c.done,
+                })
+                .collect(),
+            date,
+            frequency,
+            repeat,
+            value: todo.cost,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RespSingleTask {
+    #[allow(dead_code)]
+    pub success: bool,
+    pub data: Task,
+}
+
+/// The body of `PUT /tasks/:taskId`
+#[derive(Serialize, Debug, Default)]
+pub struct UpdateTaskBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// `Some(None)` removes the due date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<Option<DueDate>>,
+}
+
+/// Return the tags to add and the tags to remove to move from `current` to `wanted`
+///
+/// Tags in `keep` are never removed
+pub fn diff_tags(
+    current: &[String],
+    wanted: &[String],
+    keep: &[String],
+) -> (Vec<String>, Vec<String>) {
+    let mut to_add: Vec<String> = Vec::new();
+    for t in wanted {
+        if !current.contains(t) && !to_add.contains(t) {
+            to_add.push(t.clone());
+        }
+    }
+    let to_remove = current
+        .iter()
+        .filter(|t| !wanted.contains(t) && !keep.contains(t))
+        .cloned()
+        .collect();
+    (to_add, to_remove)
+}
+
+/// The requests needed to move a checklist from its current state to the wanted one
+#[derive(Debug, Default)]
+pub struct ChecklistChanges {
+    pub to_add: Vec<CreateChecklistItem>,
+    /// Existing items that changed, with their ID
+    pub to_update: Vec<(String, CreateChecklistItem)>,
+    /// IDs of the items to remove
+    pub to_remove: Vec<String>,
+}
+
+impl ChecklistChanges {
+    pub fn new(current: &[Checklist], wanted: &[CheckListItem]) -> Self {
+        let mut result = ChecklistChanges::default();
+        // IDs of the existing items to keep; items without ID keep the existing item
+        // with the same text, so that its ID and its state aren't lost
+        let mut kept: Vec<&str> = wanted.iter().filter_map(|i| i.id.as_deref()).collect();
+        for item in wanted {
+            let body = CreateChecklistItem {
+                text: item.name.clone(),
+                completed: item.done,
+            };
+            match &item.id {
+                Some(id) => match current.iter().find(|c| &c.id == id) {
+                    None => result.to_add.push(body),
+                    Some(c) if c.text != item.name || c.completed != item.done => {
+                        result.to_update.push((c.id.clone(), body))
+                    }
+                    Some(_) => (),
+                },
+                None => match current
+                    .iter()
+                    .find(|c| c.text == item.name && !kept.contains(&c.id.as_str()))
+                {
+                    Some(c) => kept.push(&c.id),
+                    None => result.to_add.push(body),
+                },
+            }
+        }
+        result.to_remove = current
+            .iter()
+            .filter(|c| !kept.contains(&c.id.as_str()))
+            .map(|c| c.id.clone())
+            .collect();
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -250,11 +703,103 @@ mod tests {
         let input = Checklist {
             completed: true,
             text: "Checklist item 1".to_string(),
-            id: "ignored".to_string(),
+            id: "ID1".to_string(),
         };
         let uut = aide_proto::v1::todo::CheckListItem::from(&input);
         assert_eq!(uut.done, input.completed);
         assert_eq!(uut.name, input.text);
+        assert_eq!(uut.id.as_deref(), Some("ID1"));
+    }
+    #[test]
+    fn test_task_conversion_keeps_id() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let input = Task {
+            id: "ID".to_string(),
+            description: "Short Description".to_string(),
+            task_type: TaskTypes::Todo,
+            notes: "Long Description".to_string(),
+            tags: Vec::new(),
+            checklist: None,
+            completed: None,
+            date: Some("2021-03-01T12:00:00.000Z".to_string()),
+            up: None,
+            down: None,
+            counter_up: None,
+            counter_down: None,
+            value: None,
+            date_completed: None,
+            frequency: None,
+            every: None,
+            repeat: None,
+        };
+        let uut = input.to_todo(&utc);
+        assert_eq!(
+            uut.due_date.unwrap().to_string(),
+            "2021-03-01T12:00:00+00:00"
+        );
+        // a date set in Habitica, at midnight in the user's timezone
+        let tz = chrono::FixedOffset::east_opt(3600).unwrap();
+        let input = Task {
+            date: Some("2021-02-28T23:00:00.000Z".to_string()),
+            ..input
+        };
+        let uut = input.to_todo(&tz);
+        assert_eq!(uut.due_date, Some("2021-03-01".parse().unwrap()));
+        assert_eq!(
+            input.to_todo(&utc).due_date.unwrap().to_string(),
+            "2021-02-28T23:00:00+00:00"
+        );
+        assert_eq!(uut.id.as_deref(), Some("ID"));
+        assert!(uut.source.is_none());
+    }
+    #[test]
+    fn test_habit_and_reward_conversion() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let input = r#"{"id":"H","text":"Walk","type":"habit","notes":"","tags":[],
+            "up":true,"down":false,"counterUp":3,"counterDown":0}"#;
+        let uut = serde_json::from_str::<Task>(input).unwrap().to_todo(&utc);
+        assert_eq!(uut.todo_type, aide_proto::v1::todo::TodoTypes::Habit);
+        let habit = uut.habit.unwrap();
+        assert!(habit.up && !habit.down);
+        assert_eq!(habit.counter_up, 3);
+        assert!(uut.cost.is_none());
+        let input = r#"{"id":"R","text":"Cake","type":"reward","notes":"","tags":[],"value":10}"#;
+        let uut = serde_json::from_str::<Task>(input).unwrap().to_todo(&utc);
+        assert_eq!(uut.todo_type, aide_proto::v1::todo::TodoTypes::Reward);
+        assert_eq!(uut.cost, Some(10.0));
+        assert!(uut.habit.is_none());
+    }
+    #[test]
+    fn test_daily_classification() {
+        use aide_proto::v1::todo::TodoTypes;
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let input = r#"{"id":"D","text":"Stretch","type":"daily","notes":"","tags":[],
+            "frequency":"weekly","everyX":1,
+            "repeat":{"su":true,"s":false,"f":false,"th":false,"w":false,"t":false,"m":false}}"#;
+        let uut = serde_json::from_str::<Task>(input).unwrap().to_todo(&utc);
+        assert_eq!(uut.todo_type, TodoTypes::Weekly);
+        let all_week = Repeat::all_week();
+        assert_eq!(daily_todo_type("daily", 1, &all_week), TodoTypes::Daily);
+        assert_eq!(daily_todo_type("daily", 2, &all_week), TodoTypes::Daily);
+        assert_eq!(daily_todo_type("daily", 7, &all_week), TodoTypes::Weekly);
+        assert_eq!(daily_todo_type("weekly", 1, &all_week), TodoTypes::Daily);
+        assert_eq!(daily_todo_type("weekly", 2, &all_week), TodoTypes::Weekly);
+        let sunday = Repeat::only_sunday();
+        assert_eq!(daily_todo_type("weekly", 1, &sunday), TodoTypes::Weekly);
+        assert_eq!(daily_todo_type("monthly", 1, &all_week), TodoTypes::Weekly);
+        assert_eq!(daily_todo_type("yearly", 1, &all_week), TodoTypes::Weekly);
+    }
+    #[test]
+    fn test_completed_todo_conversion() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let input = r#"{"id":"C","text":"Report","type":"todo","notes":"","tags":[],
+            "completed":true,"dateCompleted":"2021-03-01T10:00:00.000Z"}"#;
+        let uut = serde_json::from_str::<Task>(input).unwrap().to_todo(&utc);
+        assert!(uut.done);
+        assert_eq!(
+            uut.completed_at.unwrap().to_rfc3339(),
+            "2021-03-01T10:00:00+00:00"
+        );
     }
     #[test]
     fn test_checklist_vector_conversion() {
@@ -268,7 +813,7 @@ mod tests {
             text: "Checklist item 2".to_string(),
             id: "ignored".to_string(),
         };
-        let input = vec![cl1, cl2];
+        let input = [cl1, cl2];
         let uut: Vec<CheckListItem> = input.iter().map(|cl| cl.into()).collect();
         assert_eq!(uut.len(), 2);
         assert_eq!(uut[0].done, input[0].completed);
@@ -299,14 +844,18 @@ mod tests {
             frequency: "weekly".to_string(),
             next_due: Vec::new(),
             every: 1,
+            start_date: None,
+            days_of_month: Vec::new(),
+            weeks_of_month: Vec::new(),
         };
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
         // without a checklist, it's a sunday only thing
         // 2021-02-27: saturday
-        let saturday = chrono::NaiveDate::from_ymd(2021, 2, 27);
-        assert!(!uut.is_due_today(&saturday));
+        let saturday = chrono::NaiveDate::from_ymd_opt(2021, 2, 27).unwrap();
+        assert!(!uut.is_due_today(&saturday, &utc));
         // 2021-02-28: sunday
-        let sunday = chrono::NaiveDate::from_ymd(2021, 2, 28);
-        assert!(uut.is_due_today(&sunday));
+        let sunday = chrono::NaiveDate::from_ymd_opt(2021, 2, 28).unwrap();
+        assert!(uut.is_due_today(&sunday, &utc));
         // with a checklist, it's a weekly thing
         let cl1 = Checklist {
             completed: false,
@@ -319,8 +868,8 @@ mod tests {
         assert!(uut.repeat.is_only_sunday());
         assert!(!uut.repeat.is_today_on(&saturday));
         assert!(uut.repeat.is_today_on(&sunday));
-        assert!(uut.is_due_today(&saturday));
-        assert!(uut.is_due_today(&sunday));
+        assert!(uut.is_due_today(&saturday, &utc));
+        assert!(uut.is_due_today(&sunday, &utc));
         // with a completed checklist, it's done, but not the task
         let cl1 = Checklist {
             completed: true,
@@ -333,8 +882,8 @@ mod tests {
         assert!(uut.repeat.is_only_sunday());
         assert!(!uut.repeat.is_today_on(&saturday));
         assert!(uut.repeat.is_today_on(&sunday));
-        assert!(!uut.is_due_today(&saturday));
-        assert!(uut.is_due_today(&sunday));
+        assert!(!uut.is_due_today(&saturday, &utc));
+        assert!(uut.is_due_today(&sunday, &utc));
     }
     #[test]
     fn test_due_daily() {
@@ -359,19 +908,278 @@ mod tests {
             frequency: "weekly".to_string(),
             next_due: Vec::new(),
             every: 1,
+            start_date: None,
+            days_of_month: Vec::new(),
+            weeks_of_month: Vec::new(),
         };
-        assert!(uut.is_due());
+        let today = DayStart::local(0).today();
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        assert!(uut.is_due_today(&today, &utc));
         uut.completed = Some(false);
-        assert!(uut.is_due());
+        assert!(uut.is_due_today(&today, &utc));
         uut.completed = Some(true);
-        assert!(!uut.is_due());
+        assert!(!uut.is_due_today(&today, &utc));
+    }
+
+    #[test]
+    fn test_day_start() {
+        use chrono::TimeZone;
+        let preferences: Preferences =
+            serde_json::from_str(r#"{"dayStart": 4, "timezoneOffset": -60}"#).unwrap();
+        let uut = DayStart::from(&preferences);
+        let date = |d| chrono::NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        // 02:30 UTC is 03:30 in the user's timezone, still the previous day
+        let now = chrono::Utc.with_ymd_and_hms(2021, 3, 2, 2, 30, 0).unwrap();
+        assert_eq!(uut.day_at(now), date(1));
+        // 03:00 UTC is 04:00 in the user's timezone, the new day
+        let now = chrono::Utc.with_ymd_and_hms(2021, 3, 2, 3, 0, 0).unwrap();
+        assert_eq!(uut.day_at(now), date(2));
+        let uut = DayStart {
+            hour: 0,
+            timezone_offset: 300,
+        };
+        let now = chrono::Utc.with_ymd_and_hms(2021, 3, 2, 3, 0, 0).unwrap();
+        assert_eq!(uut.day_at(now), date(1));
+    }
+
+    #[test]
+    fn test_create_task_body() {
+        use aide_proto::v1::todo::TodoTypes;
+        let mut todo = Todo::from("Buy milk".to_string());
+        todo.checklist.push(CheckListItem {
+            id: None,
+            name: "Whole".to_string(),
+            done: false,
+        });
+        todo.due_date = Some("2021-03-01".parse().unwrap());
+        let tz = chrono::FixedOffset::east_opt(3600).unwrap();
+        let uut =
+            serde_json::to_value(CreateTaskBody::new(&todo, vec!["ID".to_string()], &tz)).unwrap();
+        assert_eq!(uut["type"], "todo");
+        assert_eq!(uut["text"], "Buy milk");
+        assert_eq!(uut["tags"][0], "ID");
+        assert_eq!(uut["checklist"][0]["text"], "Whole");
+        assert_eq!(uut["date"], "2021-03-01T00:00:00+01:00");
+        assert!(uut.get("notes").is_none());
+        assert!(uut.get("repeat").is_none());
+        todo.todo_type = TodoTypes::Weekly;
+        let uut = serde_json::to_value(CreateTaskBody::new(&todo, Vec::new(), &tz)).unwrap();
+        assert_eq!(uut["type"], "daily");
+        assert_eq!(uut["frequency"], "weekly");
+        assert_eq!(uut["repeat"]["su"], true);
+        assert_eq!(uut["repeat"]["m"], false);
+        assert!(uut.get("date").is_none());
+    }
+
+    #[test]
+    fn test_diff_tags() {
+        let s = |v: &[&str]| v.iter().map(|t| t.to_string()).collect::<Vec<String>>();
+        let (to_add, to_remove) = diff_tags(
+            &s(&["a", "b", "daily"]),
+            &s(&["b", "c", "c"]),
+            &s(&["daily"]),
+        );
+        assert_eq!(to_add, s(&["c"]));
+        assert_eq!(to_remove, s(&["a"]));
+    }
+    #[test]
+    fn test_checklist_changes() {
+        let current = vec![
+            Checklist {
+                completed: false,
+                text: "same".to_string(),
+                id: "ID1".to_string(),
+            },
+            Checklist {
+                completed: false,
+                text: "typo".to_string(),
+                id: "ID2".to_string(),
+            },
+            Checklist {
+                completed: true,
+                text: "gone".to_string(),
+                id: "ID3".to_string(),
+            },
+        ];
+        let wanted = vec![
+            CheckListItem {
+                id: Some("ID1".to_string()),
+                name: "same".to_string(),
+                done: false,
+            },
+            CheckListItem {
+                id: Some("ID2".to_string()),
+                name: "fixed".to_string(),
+                done: false,
+            },
+            CheckListItem {
+                id: None,
+                name: "new".to_string(),
+                done: false,
+            },
+        ];
+        let uut = ChecklistChanges::new(&current, &wanted);
+        assert_eq!(uut.to_add.len(), 1);
+        assert_eq!(uut.to_add[0].text, "new");
+        assert_eq!(uut.to_update.len(), 1);
+        assert_eq!(uut.to_update[0].0, "ID2");
+        assert_eq!(uut.to_update[0].1.text, "fixed");
+        assert_eq!(uut.to_remove, vec!["ID3".to_string()]);
+        // items without ID keep the existing items with the same text, done or not
+        let wanted: Vec<CheckListItem> = ["gone", "same", "new"]
+            .iter()
+            .map(|name| CheckListItem {
+                id: None,
+                name: name.to_string(),
+                done: false,
+            })
+            .collect();
+        let uut = ChecklistChanges::new(&current, &wanted);
+        assert_eq!(uut.to_add.len(), 1);
+        assert_eq!(uut.to_add[0].text, "new");
+        assert!(uut.to_update.is_empty());
+        assert_eq!(uut.to_remove, vec!["ID2".to_string()]);
+    }
+
+    fn daily(frequency: &str, every: u32, start_date: &str) -> Daily {
+        Daily {
+            id: "ID".to_string(),
+            description: "Short Description".to_string(),
+            task_type: TaskTypes::Daily,
+            notes: "Long Description".to_string(),
+            tags: Vec::new(),
+            checklist: None,
+            completed: None,
+            repeat: Repeat::all_week(),
+            frequency: frequency.to_string(),
+            next_due: Vec::new(),
+            every,
+            start_date: Some(start_date.to_string()),
+            days_of_month: Vec::new(),
+            weeks_of_month: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_every_other_day() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let date = |d| chrono::NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        let uut = daily("daily", 2, "2021-03-01T00:00:00.000Z");
+        assert!(uut.is_due_today(&date(1), &utc));
+        assert!(!uut.is_due_today(&date(2), &utc));
+        assert!(uut.is_due_today(&date(3), &utc));
+        // not started yet
+        let uut = daily("daily", 1, "2021-03-05T00:00:00.000Z");
+        assert!(!uut.is_due_today(&date(4), &utc));
+        assert!(uut.is_due_today(&date(5), &utc));
+        // the start date is in the user's timezone
+        let cet = chrono::FixedOffset::east_opt(3600).unwrap();
+        let uut = daily("daily", 2, "2021-02-28T23:00:00.000Z");
+        assert!(!uut.is_due_today(&date(1), &utc));
+        assert!(uut.is_due_today(&date(1), &cet));
+    }
+
+    #[test]
+    fn test_every_other_week() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let date = |d| chrono::NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        // 2021-03-01 is a monday
+        let mut uut = daily("weekly", 2, "2021-03-01T00:00:00.000Z");
+        uut.repeat = Repeat {
+            m: true,
+            su: false,
+            ..Repeat::only_sunday()
+        };
+        assert!(uut.is_due_today(&date(1), &utc));
+        assert!(!uut.is_due_today(&date(2), &utc));
+        assert!(!uut.is_due_today(&date(8), &utc));
+        assert!(uut.is_due_today(&date(15), &utc));
+    }
+
+    #[test]
+    fn test_monthly() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let date = |m, d| chrono::NaiveDate::from_ymd_opt(2021, m, d).unwrap();
+        let mut uut = daily("monthly", 1, "2021-01-10T00:00:00.000Z");
+        uut.days_of_month = vec![10];
+        assert!(uut.is_due_today(&date(3, 10), &utc));
+        assert!(!uut.is_due_today(&date(3, 11), &utc));
+        uut.every = 2;
+        assert!(!uut.is_due_today(&date(2, 10), &utc));
+        assert!(uut.is_due_today(&date(3, 10), &utc));
+        // the second tuesday of the month
+        let mut uut = daily("monthly", 1, "2021-01-10T00:00:00.000Z");
+        uut.weeks_of_month = vec![1];
+        uut.repeat = Repeat {
+            t: true,
+            su: false,
+            ..Repeat::only_sunday()
+        };
+        assert!(!uut.is_due_today(&date(3, 2), &utc));
+        assert!(uut.is_due_today(&date(3, 9), &utc));
+        assert!(!uut.is_due_today(&date(3, 10), &utc));
+    }
+
+    #[test]
+    fn test_yearly() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let uut = daily("yearly", 1, "2020-03-01T00:00:00.000Z");
+        let date = |y, d| chrono::NaiveDate::from_ymd_opt(y, 3, d).unwrap();
+        assert!(uut.is_due_today(&date(2021, 1), &utc));
+        assert!(!uut.is_due_today(&date(2021, 2), &utc));
+    }
+
+    #[test]
+    fn test_every_other_week_with_checklist() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let date = |d| chrono::NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        // 2021-02-28 is a sunday
+        let mut uut = daily("weekly", 2, "2021-02-28T00:00:00.000Z");
+        uut.repeat = Repeat::only_sunday();
+        uut.checklist = Some(vec![Checklist {
+            completed: false,
+            text: "Checklist item 1".to_string(),
+            id: "ignored".to_string(),
+        }]);
+        assert!(uut.is_due_today(&date(3), &utc));
+        // the week skipped by everyX, sunday included
+        assert!(!uut.is_due_today(&date(7), &utc));
+        assert!(!uut.is_due_today(&date(10), &utc));
+        assert!(uut.is_due_today(&date(14), &utc));
+        assert!(uut.is_due_today(&date(17), &utc));
+    }
+
+    #[test]
+    fn test_weekly_due_date() {
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        let mut uut = daily("weekly", 1, "2021-01-01T00:00:00.000Z");
+        uut.repeat = Repeat::only_sunday();
+        uut.checklist = Some(vec![Checklist {
+            completed: false,
+            text: "Checklist item 1".to_string(),
+            id: "ignored".to_string(),
+        }]);
+        // 2021-03-03 is a wednesday
+        let today = chrono::NaiveDate::from_ymd_opt(2021, 3, 3).unwrap();
+        let todo = uut.to_todo(&today, &utc);
+        assert_eq!(todo.due_date, Some("2021-03-07".parse().unwrap()));
+        uut.next_due = vec![
+            "Wed Mar 03 2021 00:00:00 GMT+0000".to_string(),
+            "Sun Mar 14 2021 00:00:00 GMT+0000".to_string(),
+        ];
+        let todo = uut.to_todo(&today, &utc);
+        assert_eq!(todo.due_date, Some("2021-03-14".parse().unwrap()));
     }
 
     #[test]
     fn test_next_due_conversion() {
+        let cet = chrono::FixedOffset::east_opt(3600).unwrap();
+        let day = chrono::NaiveDate::from_ymd_opt(2020, 12, 9);
         let next_due = "Wed Dec 09 2020 00:00:00 GMT+0100";
-        assert!(
-            chrono::NaiveDateTime::parse_from_str(next_due, "%a %b %d %Y %H:%M:%S GMT%z").is_ok()
-        )
+        assert_eq!(parse_daily_date(next_due, &cet), day);
+        let next_due = "Wed Dec 09 2020 00:00:00 GMT+0100 (Central European Standard Time)";
+        assert_eq!(parse_daily_date(next_due, &cet), day);
+        assert_eq!(parse_daily_date("2020-12-08T23:00:00.000Z", &cet), day);
+        assert_eq!(parse_daily_date("tomorrow", &cet), None);
     }
 }
@@ -1,9 +1,17 @@
 const BASE_URL_V3: &str = "https://habitica.com/api/v3/";
+use super::upstream::UpstreamError;
 use anyhow::anyhow;
+use log::{debug, warn};
 use reqwest_pool::ReqwestPool;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::Instant;
+
+/// The shortest time between two reloads of the tags caused by cache misses, so that
+/// unknown tags don't cause a reload per request
+const MIN_MISS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
 
 // hashmap with K = tag_id, V = tag_name
 type TagCache = Arc<RwLock<HashMap<String, String>>>;
@@ -15,47 +23,107 @@ pub struct HabiticaState {
     pub client_id: String,
     pub pool: ReqwestPool,
     pub tag_cache: TagCache,
+    /// When the tags were last reloaded
+    pub tags_reloaded_at: Arc<Mutex<Option<Instant>>>,
+    /// When a new Habitica day starts, to know which dailies are due
+    pub day_start: super::habitica::DayStart,
+    /// How dailies are classified as daily or weekly todos
+    pub classification: super::cli::Classification,
+    /// Shared by all the requests to Habitica
+    pub rate_limit: super::upstream::RateLimit,
+    /// The token aide clients have to provide
+    pub api_token: Option<String>,
 }
 
 #[derive(strum::Display, Debug, Clone, PartialEq)]
 pub enum UsersTaskTypes {
-    #[allow(dead_code)]
-    #[strum(serialize = "habit")]
+    #[strum(serialize = "habits")]
     Habits,
     #[strum(serialize = "todos")]
     Todos,
-    #[allow(dead_code)]
-    #[strum(serialize = "reward")]
+    #[strum(serialize = "rewards")]
     Rewards,
     #[strum(serialize = "dailys")]
     Dailys,
-    #[allow(dead_code)]
     #[strum(serialize = "completedTodos")]
     CompletedTodos,
 }
 
+/// Reload all the tags from Habitica, forgetting the deleted ones
 pub async fn fill_tag_cache(state: HabiticaState) -> Result<(), anyhow::Error> {
     let base_url = reqwest::Url::parse(BASE_URL_V3)?;
     let tags_url = base_url.join("tags")?;
-    let handler = state.pool.get_handler().await?;
-    let client = handler.get_client();
-    let response = client
-        .get(tags_url)
-        .header("x-client", state.client_id.clone())
-        .header("x-api-user", state.user.clone())
-        .header("x-api-key", state.key.clone())
-        .send()
-        .await?;
-    let resp_tags: super::habitica::RespTags = response.json().await?;
-    drop(handler);
-    let mut unlocked_cache = state.tag_cache.write().await;
-    for tag in resp_tags.data.iter() {
-        unlocked_cache.insert(tag.id.clone(), tag.name.clone());
-    }
+    let response = send(&state, |client| client.get(tags_url)).await?;
+    let resp_tags: super::habitica::RespTags = check_response(response).await?.json().await?;
+    let tags = resp_tags
+        .data
+        .into_iter()
+        .map(|tag| (tag.id, tag.name))
+        .collect();
+    *state.tag_cache.write().await = tags;
+    *state.tags_reloaded_at.lock().await = Some(Instant::now());
     Ok(())
 }
 
+/// Read when a new day starts from the preferences of the user
+pub async fn get_day_start(
+    state: &HabiticaState,
+) -> Result<super::habitica::DayStart, anyhow::Error> {
+    let base_url = reqwest::Url::parse(BASE_URL_V3)?;
+    let mut user_url = base_url.join("user")?;
+    user_url.set_query(Some(
+        "userFields=preferences.dayStart,preferences.timezoneOffset",
+    ));
+    let response = send(state, |client| client.get(user_url)).await?;
+    let response = check_response(response).await?;
+    let resp_user: super::habitica::RespUser = response.json().await?;
+    Ok((&resp_user.data.preferences).into())
+}
+
+/// Reload the tags every `interval`, to catch the ones edited outside aide
+pub async fn refresh_tag_cache(state: HabiticaState, interval: std::time::Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = fill_tag_cache(state.clone()).await {
+            warn!("Failed to refresh the tags [{}]", e);
+        }
+    }
+}
+
+/// Whether a cache miss at `now` should reload the tags, last reloaded at `last`
+fn miss_reload_due(last: Option<Instant>, now: Instant) -> bool {
+    last.is_none_or(|last| now.saturating_duration_since(last) >= MIN_MISS_RELOAD_INTERVAL)
+}
+
+/// Reload the tags after a cache miss, unless they were reloaded recently; failures
+/// are only logged, as the cache is still usable
+async fn refresh_on_miss(state: &HabiticaState) {
+    {
+        let mut reloaded_at = state.tags_reloaded_at.lock().await;
+        let now = Instant::now();
+        if !miss_reload_due(*reloaded_at, now) {
+            debug!("Tag cache miss, the tags were reloaded recently");
+            return;
+        }
+        // claimed before reloading, so that concurrent misses don't reload too
+        *reloaded_at = Some(now);
+    }
+    debug!("Tag cache miss, reloading the tags");
+    if let Err(e) = fill_tag_cache(state.clone()).await {
+        warn!("Failed to reload the tags [{}]", e);
+    }
+}
+
 pub async fn replace_tag_id(todos: &mut [aide_proto::v1::todo::Todo], state: &HabiticaState) {
+    let tags_unlocked = state.tag_cache.read().await;
+    let missing = todos
+        .iter()
+        .flat_map(|t| t.tags.iter())
+        .any(|tag| !tags_unlocked.contains_key(tag));
+    drop(tags_unlocked);
+    if missing {
+        refresh_on_miss(state).await;
+    }
     for t in todos {
         let tags_unlocked = state.tag_cache.read().await;
         for tag in t.tags.iter_mut() {
@@ -67,6 +135,14 @@ pub async fn replace_tag_id(todos: &mut [aide_proto::v1::todo::Todo], state: &Ha
 }
 
 pub async fn get_tag_id(state: &HabiticaState, label: &str) -> Option<String> {
+    if let Some(id) = find_tag_id(state, label).await {
+        return Some(id);
+    }
+    refresh_on_miss(state).await;
+    find_tag_id(state, label).await
+}
+
+async fn find_tag_id(state: &HabiticaState, label: &str) -> Option<String> {
     let cache = state.tag_cache.read().await;
     cache
         .iter()
@@ -83,37 +159,35 @@ pub async fn get_tasks(
     let base_url = reqwest::Url::parse(BASE_URL_V3).unwrap();
     let mut todo_url = base_url.join("tasks/user").unwrap();
     todo_url.set_query(Some(&format!("type={}", task_type)));
-    let handler = state.pool.get_handler().await?;
-    let client = handler.get_client();
-    let response = client
-        .get(todo_url)
-        .header("x-client", state.client_id.clone())
-        .header("x-api-user", state.user.clone())
-        .header("x-api-key", state.key.clone())
-        .send()
-        .await?;
+    let response = send(state, |client| client.get(todo_url)).await?;
     match task_type {
         UsersTaskTypes::Dailys => {
-            let resp_daily: RespDaily = response.json().await?;
-            drop(handler);
+            let resp_daily: RespDaily = check_response(response).await?.json().await?;
+            let today = state.day_start.today();
+            let timezone = state.day_start.timezone();
             let todos: Vec<aide_proto::v1::todo::Todo> = resp_daily
                 .data
                 .iter()
-                .filter(|d| d.is_due())
-                .map(|t| t.into())
+                .filter(|d| d.is_due_today(&today, &timezone))
+                .map(|d| d.to_todo(&today, &timezone))
                 .collect();
             //debug!("received from habitica {} todos", todos.len());
             Ok(todos)
         }
-        UsersTaskTypes::Todos => {
-            let resp_task: RespTask = response.json().await?;
-            drop(handler);
-            let todos: Vec<aide_proto::v1::todo::Todo> =
-                resp_task.data.iter().map(|t| t.into()).collect();
+        UsersTaskTypes::Todos
+        | UsersTaskTypes::Habits
+        | UsersTaskTypes::Rewards
+        | UsersTaskTypes::CompletedTodos => {
+            let resp_task: RespTask = check_response(response).await?.json().await?;
+            let timezone = state.day_start.timezone();
+            let todos: Vec<aide_proto::v1::todo::Todo> = resp_task
+                .data
+                .iter()
+                .map(|t| t.to_todo(&timezone))
+                .collect();
             //debug!("received from habitica {} todos", todos.len());
             Ok(todos)
         }
-        _ => Ok(vec![]),
     }
 }
 
@@ -121,46 +195,46 @@ pub async fn get_all_tasks(
     state: &HabiticaState,
 ) -> Result<Vec<aide_proto::v1::todo::Todo>, anyhow::Error> {
     let mut result = get_tasks(state, UsersTaskTypes::Todos).await?;
-    let dailys = get_tasks(state, UsersTaskTypes::Dailys).await?;
-    let daily_tag_id = get_tag_id(state, "daily").await.unwrap_or_default();
-    let weekly_tag_id = get_tag_id(state, "weekly").await.unwrap_or_default();
-    let mut filtered_dailys: Vec<aide_proto::v1::todo::Todo> = dailys
-        .iter()
-        .filter(|d| {
+    result.append(&mut get_dailys(state).await?);
+    Ok(result)
+}
+
+/// Get the due dailies, as daily and weekly todos
+///
+/// With the tags classification, only the dailies with the "daily" or the "weekly"
+/// label are returned
+pub async fn get_dailys(
+    state: &HabiticaState,
+) -> Result<Vec<aide_proto::v1::todo::Todo>, anyhow::Error> {
+    use aide_proto::v1::todo::TodoTypes;
+    let mut dailys = get_tasks(state, UsersTaskTypes::Dailys).await?;
+    if state.classification == super::cli::Classification::Tags {
+        let daily_tag_id = get_tag_id(state, "daily").await.unwrap_or_default();
+        let weekly_tag_id = get_tag_id(state, "weekly").await.unwrap_or_default();
+        dailys.retain(|d| {
             d.tags
                 .iter()
                 .any(|t| t == &daily_tag_id || t == &weekly_tag_id)
-        })
-        .map(|d| {
-            let mut new_daily = d.to_owned();
-            if new_daily.tags.contains(&weekly_tag_id) {
-                new_daily.todo_type = aide_proto::v1::todo::TodoTypes::Weekly;
-            }
-            new_daily
-        })
-        .collect();
-    result.append(&mut filtered_dailys);
-    Ok(result)
+        });
+        for d in dailys.iter_mut() {
+            d.todo_type = if d.tags.contains(&weekly_tag_id) {
+                TodoTypes::Weekly
+            } else {
+                TodoTypes::Daily
+            };
+        }
+    }
+    Ok(dailys)
 }
 
 pub async fn create_label(state: &HabiticaState, label: &str) -> Result<(), anyhow::Error> {
     let base_url = reqwest::Url::parse(BASE_URL_V3)?;
     let tags_url = base_url.join("tags")?;
-    let handler = state.pool.get_handler().await?;
-    let client = handler.get_client();
     let body = super::habitica::CreateTagBody {
         name: label.to_string(),
     };
-    let response = client
-        .post(tags_url)
-        .json(&body)
-        .header("x-client", state.client_id.clone())
-        .header("x-api-user", state.user.clone())
-        .header("x-api-key", state.key.clone())
-        .send()
-        .await?;
-    let resp: super::habitica::RespCreateTag = response.json().await?;
-    drop(handler);
+    let response = send(state, |client| client.post(tags_url).json(&body)).await?;
+    let resp: super::habitica::RespCreateTag = check_response(response).await?.json().await?;
     let mut unlocked_cache = state.tag_cache.write().await;
     unlocked_cache.insert(resp.data.id, resp.data.name);
 
@@ -174,25 +248,242 @@ pub async fn delete_label(state: &HabiticaState, label: &str) -> Result<(), anyh
     let base_url = reqwest::Url::parse(BASE_URL_V3)?;
     let tags_url = base_url.join("tags/")?;
     let delete_url = tags_url.join(&tag_id)?;
-    let handler = state.pool.get_handler().await?;
-    let client = handler.get_client();
-    let response = client
-        .delete(delete_url)
-        .header("x-client", state.client_id.clone())
-        .header("x-api-user", state.user.clone())
-        .header("x-api-key", state.key.clone())
-        .send()
-        .await?;
-    if response.status() == hyper::StatusCode::OK {
-        let resp: super::habitica::RespGeneric = response.json().await?;
-        if resp.success {
-            drop(handler);
-            let mut unlocked_cache = state.tag_cache.write().await;
-            unlocked_cache.remove(&tag_id);
-            return Ok(());
-        } else {
-            return Err(anyhow!("Delete of label {} not successful", label));
+    let response = send(state, |client| client.delete(delete_url)).await?;
+    let resp: super::habitica::RespGeneric = check_response(response).await?.json().await?;
+    if resp.success {
+        let mut unlocked_cache = state.tag_cache.write().await;
+        unlocked_cache.remove(&tag_id);
+        Ok(())
+    } else {
+        Err(anyhow!("Delete of label {} not successful", label))
+    }
+}
+
+/// Create a task from a todo, returning the todo as stored by Habitica
+///
+/// The tags of the todo have to be already resolved to IDs
+pub async fn create_task(
+    state: &HabiticaState,
+    todo: &aide_proto::v1::todo::Todo,
+    tag_ids: Vec<String>,
+) -> Result<aide_proto::v1::todo::Todo, anyhow::Error> {
+    let base_url = reqwest::Url::parse(BASE_URL_V3)?;
+    let tasks_url = base_url.join("tasks/user")?;
+    let body = super::habitica::CreateTaskBody::new(todo, tag_ids, &state.day_start.timezone());
+    let response = send(state, |client| client.post(tasks_url).json(&body)).await?;
+    let response = check_response(response).await?;
+    let resp: super::habitica::RespSingleTask = response.json().await?;
+    let mut result: aide_proto::v1::todo::Todo = resp.data.to_todo(&state.day_start.timezone());
+    result.todo_type = todo.todo_type;
+    // only tasks have a due date in Habitica, the one of dailies depends on their schedule
+    if todo.todo_type == aide_proto::v1::todo::TodoTypes::Task {
+        result.due_date = todo.due_date;
+    }
+    replace_tag_id(std::slice::from_mut(&mut result), state).await;
+    Ok(result)
+}
+
+#[derive(strum::Display, strum::EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum ScoreDirection {
+    #[strum(serialize = "up")]
+    Up,
+    #[strum(serialize = "down")]
+    Down,
+}
+
+/// Score a task up (mark it as done) or down (mark it as not done)
+///
+/// It returns false if Habitica has no task with this ID
+pub async fn score_task(
+    state: &HabiticaState,
+    task_id: &str,
+    direction: ScoreDirection,
+) -> Result<bool, anyhow::Error> {
+    let base_url = reqwest::Url::parse(BASE_URL_V3)?;
+    let score_url = base_url.join(&format!("tasks/{}/score/{}", task_id, direction))?;
+    let response = send(state, |client| client.post(score_url)).await?;
+    if response.status() == hyper::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    let response = check_response(response).await?;
+    let resp: super::habitica::RespGeneric = response.json().await?;
+    if resp.success {
+        Ok(true)
+    } else {
+        Err(anyhow!(
+            "Score {} of task {} not successful",
+            direction,
+            task_id
+        ))
+    }
+}
+
+/// Toggle the state of a checklist item, returning the item as updated by Habitica
+///
+/// It returns None if Habitica has no such task or checklist item
+pub async fn score_checklist_item(
+    state: &HabiticaState,
+    task_id: &str,
+    item_id: &str,
+) -> Result<Option<aide_proto::v1::todo::CheckListItem>, anyhow::Error> {
+    let base_url = reqwest::Url::parse(BASE_URL_V3)?;
+    let score_url = base_url.join(&format!("tasks/{}/checklist/{}/score", task_id, item_id))?;
+    let response = send(state, |client| client.post(score_url)).await?;
+    if response.status() == hyper::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = check_response(response).await?;
+    let resp: super::habitica::RespSingleTask = response.json().await?;
+    let todo: aide_proto::v1::todo::Todo = resp.data.to_todo(&state.day_start.timezone());
+    Ok(todo
+        .checklist
+        .into_iter()
+        .find(|item| item.id.as_deref() == Some(item_id)))
+}
+
+/// Send a request to Habitica, with the authentication headers and respecting the rate
+/// limits
+///
+/// `build` builds the request with a client of the pool; the client is then taken from
+/// the pool only while each attempt is in flight
+async fn send(
+    state: &HabiticaState,
+    build: impl FnOnce(&reqwest::Client) -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, UpstreamError> {
+    let request = {
+        let handler = state.pool.get_handler().await?;
+        build(handler.get_client())
+            .header("x-client", state.client_id.clone())
+            .header("x-api-user", state.user.clone())
+            .header("x-api-key", state.key.clone())
+            .build()?
+    };
+    state.rate_limit.send(&state.pool, request).await
+}
+
+/// Fail if Habitica didn't reply with a success
+async fn check_response(response: reqwest::Response) -> Result<reqwest::Response, UpstreamError> {
+    UpstreamError::check(response).await
+}
+
+/// Update a task, returning the updated todo
+///
+/// Tags and checklist items are diffed against the current state of the task, sending
+/// one request per change. The tags of `update` have to be already resolved to IDs.
+/// It returns None if Habitica has no task with this ID
+pub async fn update_task(
+    state: &HabiticaState,
+    task_id: &str,
+    update: &aide_proto::v1::todo::TodoUpdate,
+) -> Result<Option<aide_proto::v1::todo::Todo>, anyhow::Error> {
+    use super::habitica::{diff_tags, ChecklistChanges, RespSingleTask, UpdateTaskBody};
+    let base_url = reqwest::Url::parse(BASE_URL_V3)?;
+    let task_url = base_url.join(&format!("tasks/{}/", task_id))?;
+    let task = base_url.join(&format!("tasks/{}", task_id))?;
+    let response = send(state, |client| client.get(task)).await?;
+    if response.status() == hyper::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let current: RespSingleTask = check_response(response).await?.json().await?;
+    if let Some(tags) = &update.tags {
+        // with the tags classification, the labels of the todo types are not managed
+        // by users
+        let mut keep = Vec::new();
+        if state.classification == super::cli::Classification::Tags {
+            for label in ["daily", "weekly"] {
+                if let Some(id) = get_tag_id(state, label).await {
+                    keep.push(id);
+                }
+            }
+        }
+        let (to_add, to_remove) = diff_tags(&current.data.tags, tags, &keep);
+        for tag_id in to_add {
+            let url = task_url.join(&format!("tags/{}", tag_id))?;
+            check_response(send(state, |client| client.post(url)).await?).await?;
+        }
+        for tag_id in to_remove {
+            let url = task_url.join(&format!("tags/{}", tag_id))?;
+            check_response(send(state, |client| client.delete(url)).await?).await?;
+        }
+    }
+    if let Some(checklist) = &update.checklist {
+        let current_checklist = current.data.checklist.clone().unwrap_or_default();
+        let changes = ChecklistChanges::new(&current_checklist, checklist);
+        for item in changes.to_add {
+            let url = task_url.join("checklist")?;
+            check_response(send(state, |client| client.post(url).json(&item)).await?).await?;
         }
+        for (item_id, item) in changes.to_update {
+            let url = task_url.join(&format!("checklist/{}", item_id))?;
+            check_response(send(state, |client| client.put(url).json(&item)).await?).await?;
+        }
+        for item_id in changes.to_remove {
+            let url = task_url.join(&format!("checklist/{}", item_id))?;
+            check_response(send(state, |client| client.delete(url)).await?).await?;
+        }
+    }
+    let body = UpdateTaskBody {
+        text: update.name.clone(),
+        notes: update.descr.clone(),
+        date: if update.clear_due_date {
+            Some(None)
+        } else {
+            let timezone = state.day_start.timezone();
+            update
+                .due_date
+                .map(|d| Some(super::habitica::habitica_date(d, &timezone)))
+        },
+    };
+    let url = base_url.join(&format!("tasks/{}", task_id))?;
+    let response = send(state, |client| client.put(url).json(&body)).await?;
+    let resp: RespSingleTask = check_response(response).await?.json().await?;
+    let mut result: aide_proto::v1::todo::Todo = resp.data.to_todo(&state.day_start.timezone());
+    if state.classification == super::cli::Classification::Tags
+        && resp.data.task_type == super::habitica::TaskTypes::Daily
+    {
+        use aide_proto::v1::todo::TodoTypes;
+        let weekly_tag_id = get_tag_id(state, "weekly").await;
+        result.todo_type = if weekly_tag_id.is_some_and(|id| result.tags.contains(&id)) {
+            TodoTypes::Weekly
+        } else {
+            TodoTypes::Daily
+        };
+    }
+    if update.due_date.is_some() || update.clear_due_date {
+        result.due_date = update.due_date;
+    }
+    replace_tag_id(std::slice::from_mut(&mut result), state).await;
+    Ok(Some(result))
+}
+
+/// Delete a task, returning false if Habitica has no task with this ID
+pub async fn delete_task(state: &HabiticaState, task_id: &str) -> Result<bool, anyhow::Error> {
+    let base_url = reqwest::Url::parse(BASE_URL_V3)?;
+    let delete_url = base_url.join(&format!("tasks/{}", task_id))?;
+    let response = send(state, |client| client.delete(delete_url)).await?;
+    if response.status() == hyper::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    let resp: super::habitica::RespGeneric = check_response(response).await?.json().await?;
+    if resp.success {
+        Ok(true)
+    } else {
+        Err(anyhow!("Delete of task {} not successful", task_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_miss_reload_due() {
+        let now = Instant::now();
+        assert!(miss_reload_due(None, now));
+        assert!(!miss_reload_due(Some(now), now));
+        assert!(!miss_reload_due(Some(now), now + Duration::from_secs(29)));
+        assert!(miss_reload_due(Some(now), now + MIN_MISS_RELOAD_INTERVAL));
+        // a reload claimed after `now` by a concurrent miss
+        assert!(!miss_reload_due(Some(now + Duration::from_secs(1)), now));
     }
-    Err(anyhow!("{}: {}", response.status(), response.text().await?))
 }
@@ -1,15 +1,20 @@
 mod cli;
 mod habitica;
 mod habitica_aide;
+mod upstream;
 const HABITICA_KEY_ENV_VAR: &str = "HABITICA_API_KEY";
 const HABITICA_USER_ENV_VAR: &str = "HABITICA_API_USER";
 const CLIENT_ID_ENV_VAR: &str = "HABITICA_CLIENT_ID";
-use aide_common::{healthz, http_404};
-use aide_proto::v1::ResultResponse;
+use aide_common::{
+    check_token, healthz, http_400, http_404, http_502, shutdown_signal, Registration,
+};
+use aide_proto::v1::{ModuleKind, ResultResponse};
 use clap::Parser;
+use habitica::DayStart;
 use habitica_aide::{get_all_tasks, get_tasks, HabiticaState};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server, Uri};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use log::{info, warn};
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -30,15 +35,41 @@ async fn main() -> Result<(), anyhow::Error> {
         .unwrap_or_else(|_| panic!("the env var {} is missing", CLIENT_ID_ENV_VAR));
     let builder = reqwest_pool::ReqwestPoolBuilder::new(1).unwrap();
     let pool = builder.build().await;
-    let state = HabiticaState {
+    let mut state = HabiticaState {
         key,
         user,
         client_id,
         pool,
         tag_cache: TagCache::default(),
+        tags_reloaded_at: Default::default(),
+        api_token: opt.common_opt.token.clone(),
+        // Habitica's default, until the user's preferences are known
+        day_start: DayStart::local(0),
+        classification: opt.classification,
+        rate_limit: upstream::RateLimit::default(),
     };
+    state.day_start = match habitica_aide::get_day_start(&state).await {
+        Ok(day_start) => day_start,
+        Err(e) => {
+            warn!("Failed to get the day start of the user [{}]", e);
+            state.day_start
+        }
+    };
+    if let Some(hour) = opt.day_start {
+        state.day_start.hour = hour;
+    }
+    info!(
+        "A new day starts at {}:00 (UTC offset {} minutes)",
+        state.day_start.hour, -state.day_start.timezone_offset
+    );
 
     habitica_aide::fill_tag_cache(state.clone()).await?;
+    if opt.tag_refresh_interval > 0 {
+        tokio::spawn(habitica_aide::refresh_tag_cache(
+            state.clone(),
+            std::time::Duration::from_secs(opt.tag_refresh_interval),
+        ));
+    }
     let service = make_service_fn(|_| {
         let cloned_state = state.clone();
         async {
@@ -47,14 +78,35 @@ async fn main() -> Result<(), anyhow::Error> {
             }))
         }
     });
-    if opt.common_opt.registration {
-        todo!("Registration not implemented yet!")
-    }
+    let registration = if opt.common_opt.registration {
+        Some(Registration::new(
+            &opt.common_opt,
+            "habitica",
+            ModuleKind::Todo,
+        )?)
+    } else {
+        None
+    };
 
     let socket_addr = std::net::SocketAddr::new(opt.common_opt.host_addr, opt.common_opt.port);
-    let server = Server::bind(&socket_addr).serve(service);
+    let server = Server::bind(&socket_addr)
+        .serve(service)
+        .with_graceful_shutdown(shutdown_signal());
+    let registration_task = registration.clone().map(|r| {
+        tokio::spawn(async move {
+            r.run().await;
+        })
+    });
     server.await?;
 
+    if let Some(task) = registration_task {
+        task.abort();
+    }
+    if let Some(r) = registration {
+        if let Err(e) = r.deregister().await {
+            warn!("Failed to deregister from aide [{}]", e);
+        }
+    }
     Ok(())
 }
 
@@ -62,19 +114,47 @@ async fn habitica_svc(
     req: Request<Body>,
     state: HabiticaState,
 ) -> Result<Response<Body>, anyhow::Error> {
-    if req.method() != Method::GET && req.method() != Method::DELETE && req.method() != Method::POST
+    let path = req.uri().path().to_string();
+    match habitica_route(req, state).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Failed to serve {} [{:#}]", path, e);
+            match e.downcast_ref::<upstream::UpstreamError>() {
+                Some(upstream_error) => Ok(upstream_error.to_response()),
+                None => Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(format!("{:#}", e)))
+                    .unwrap()),
+            }
+        }
+    }
+}
+
+async fn habitica_route(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    if req.method() != Method::GET
+        && req.method() != Method::DELETE
+        && req.method() != Method::POST
+        && req.method() != Method::PUT
     {
         return Ok(http_404(
-            &"The only methods supported are GET, POST and DELETE",
+            &"The only methods supported are GET, POST, PUT and DELETE",
         ));
     }
     if req.uri().path() == "/healthz" {
         return Ok(healthz());
     }
+    if let Some(response) = check_token(&req, state.api_token.as_deref()) {
+        return Ok(response);
+    }
     if !req.uri().path().starts_with("/v1") {
         return Ok(http_404(&"Invalid path"));
     }
-    if req.uri().path().starts_with("/v1/types") {
+    if req.uri().path() == "/v1/admin/reload-labels" && req.method() == Method::POST {
+        return reload_labels(state).await;
+    } else if req.uri().path().starts_with("/v1/types") {
         return types(req, state).await;
     } else if req.uri().path().starts_with("/v1/labels") {
         return labels(req, state).await;
@@ -84,6 +164,18 @@ async fn habitica_svc(
     Ok(http_404(&""))
 }
 
+// POST /v1/admin/reload-labels
+async fn reload_labels(state: HabiticaState) -> Result<Response<Body>, anyhow::Error> {
+    if let Err(e) = habitica_aide::fill_tag_cache(state.clone()).await {
+        warn!("Failed to reload the labels [{}]", e);
+        return Ok(http_502(&"Failed to reload the labels from Habitica"));
+    }
+    let response = ResultResponse { success: true };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
 async fn types(req: Request<Body>, state: HabiticaState) -> Result<Response<Body>, anyhow::Error> {
     use aide_proto::v1::{todo::TodoTypes, DataResponseRef};
     use strum::VariantNames;
@@ -122,7 +214,6 @@ async fn type_todos(
     let type_str = path[0];
 
     if let Ok(todo_type) = TodoTypes::from_str(type_str) {
-        use habitica_aide::get_tag_id;
         match todo_type {
             TodoTypes::Task => {
                 let todos = get_tasks(&state, UsersTaskTypes::Todos).await?;
@@ -130,50 +221,29 @@ async fn type_todos(
                     .body(Body::from(serde_json::to_string(&todos).unwrap()))
                     .unwrap())
             }
-            TodoTypes::Daily => {
-                if let Some(tag_id) = get_tag_id(&state, "daily").await {
-                    let dailys = get_tasks(&state, UsersTaskTypes::Dailys).await?;
-                    let mut filtered_dailys: Vec<aide_proto::v1::todo::Todo> = dailys
-                        .iter()
-                        .filter(|d| d.tags.iter().any(|t| t == &tag_id))
-                        .map(|d| {
-                            let mut owned_daily = d.to_owned();
-                            owned_daily.todo_type = todo_type;
-                            owned_daily
-                        })
-                        .collect();
-                    replace_tag_id(&mut filtered_dailys, &state).await;
-                    Ok(Response::builder()
-                        .body(Body::from(serde_json::to_string(&filtered_dailys).unwrap()))
-                        .unwrap())
-                } else {
-                    Ok(http_404(&"daily label not found"))
-                }
+            TodoTypes::Daily | TodoTypes::Weekly => {
+                let mut dailys = habitica_aide::get_dailys(&state).await?;
+                dailys.retain(|d| d.todo_type == todo_type);
+                replace_tag_id(&mut dailys, &state).await;
+                Ok(Response::builder()
+                    .body(Body::from(serde_json::to_string(&dailys).unwrap()))
+                    .unwrap())
             }
-
-            TodoTypes::Weekly => {
-                if let Some(tag_id) = get_tag_id(&state, "weekly").await {
-                    let dailys = get_tasks(&state, UsersTaskTypes::Dailys).await?;
-                    let mut filtered_dailys: Vec<aide_proto::v1::todo::Todo> = dailys
-                        .iter()
-                        .filter(|d| d.tags.iter().any(|t| t == &tag_id))
-                        .map(|d| {
-                            let mut owned_daily = d.to_owned();
-                            owned_daily.todo_type = todo_type;
-                            owned_daily
-                        })
-                        .collect();
-                    replace_tag_id(&mut filtered_dailys, &state).await;
-                    Ok(Response::builder()
-                        .body(Body::from(serde_json::to_string(&filtered_dailys).unwrap()))
-                        .unwrap())
+            TodoTypes::Habit | TodoTypes::Reward => {
+                let task_type = if todo_type == TodoTypes::Habit {
+                    UsersTaskTypes::Habits
                 } else {
-                    Ok(http_404(&"weekly label not found"))
-                }
+                    UsersTaskTypes::Rewards
+                };
+                let mut todos = get_tasks(&state, task_type).await?;
+                replace_tag_id(&mut todos, &state).await;
+                Ok(Response::builder()
+                    .body(Body::from(serde_json::to_string(&todos).unwrap()))
+                    .unwrap())
             }
         }
     } else {
-        return Ok(http_404(&format!("Type not supported: {}", type_str)));
+        Ok(http_404(&format!("Type not supported: {}", type_str)))
     }
 }
 
@@ -185,9 +255,16 @@ async fn labels(req: Request<Body>, state: HabiticaState) -> Result<Response<Bod
     } else if req.uri().path() == "/v1/labels" {
         use aide_proto::v1::{todo::TodoTypes, DataResponse};
         let unlocked_cache = state.tag_cache.read().await;
+        // the labels of the todo types are not managed by users
         let data: Vec<String> = unlocked_cache
             .values()
-            .filter(|l| TodoTypes::from_str(l).is_err())
+            .filter(|l| {
+                state.classification != cli::Classification::Tags
+                    || !matches!(
+                        TodoTypes::from_str(l),
+                        Ok(TodoTypes::Daily | TodoTypes::Weekly)
+                    )
+            })
             .map(|l| l.to_string())
             .collect();
         drop(unlocked_cache);
@@ -278,13 +355,22 @@ async fn label_todos(
 }
 
 async fn todos(req: Request<Body>, state: HabiticaState) -> Result<Response<Body>, anyhow::Error> {
-    use habitica_aide::replace_tag_id;
-    if req.uri().path() == "/v1/todos" {
-        let mut todos = get_all_tasks(&state).await?;
-        replace_tag_id(&mut todos, &state).await;
-        Ok(Response::builder()
-            .body(Body::from(serde_json::to_string(&todos).unwrap()))
-            .unwrap())
+    if req.uri().path() == "/v1/todos" && req.method() == Method::POST {
+        add_todo(req, state).await
+    } else if req.uri().path().ends_with("/done") {
+        done_todo(req, state).await
+    } else if req.uri().path().ends_with("/toggle") && req.method() == Method::POST {
+        toggle_checklist_item(req, state).await
+    } else if url_to_pathvec(req.uri()).len() == 3 && req.method() == Method::PUT {
+        edit_todo(req, state).await
+    } else if url_to_pathvec(req.uri()).len() == 3 && req.method() == Method::DELETE {
+        delete_todo(req, state).await
+    } else if url_to_pathvec(req.uri()).len() == 5 && req.method() == Method::POST {
+        score_todo(req, state).await
+    } else if req.uri().path() == "/v1/todos" {
+        list_todos(req, state).await
+    } else if req.uri().path() == "/v1/todos/completed" && req.method() == Method::GET {
+        list_completed_todos(req, state).await
     } else {
         Ok(http_404(&format!(
             "path not recognized: {}",
@@ -293,6 +379,247 @@ async fn todos(req: Request<Body>, state: HabiticaState) -> Result<Response<Body
     }
 }
 
+// GET /v1/todos[?type=:types&label=:labels&...]
+async fn list_todos(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    use aide_proto::v1::{todo::TodoTypes, TodoQuery};
+    use habitica_aide::{replace_tag_id, UsersTaskTypes};
+    let query = match TodoQuery::from_query(req.uri().query()) {
+        Ok(q) => q,
+        Err(e) => return Ok(http_400(&e)),
+    };
+    let wanted = |t: TodoTypes| {
+        query.types.contains(&t) || (query.types.is_empty() && t.is_listed_by_default())
+    };
+    let mut todos = if wanted(TodoTypes::Daily) || wanted(TodoTypes::Weekly) {
+        get_all_tasks(&state).await?
+    } else if wanted(TodoTypes::Task) {
+        get_tasks(&state, UsersTaskTypes::Todos).await?
+    } else {
+        Vec::new()
+    };
+    if wanted(TodoTypes::Habit) {
+        todos.append(&mut get_tasks(&state, UsersTaskTypes::Habits).await?);
+    }
+    if wanted(TodoTypes::Reward) {
+        todos.append(&mut get_tasks(&state, UsersTaskTypes::Rewards).await?);
+    }
+    replace_tag_id(&mut todos, &state).await;
+    todos.retain(|t| query.matches(t));
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&todos).unwrap()))
+        .unwrap())
+}
+
+// GET /v1/todos/completed[?since=:date&label=:labels]
+// Habitica keeps only the last 30 completed todos
+async fn list_completed_todos(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    use aide_proto::v1::TodoQuery;
+    use habitica_aide::{replace_tag_id, UsersTaskTypes};
+    let query = match TodoQuery::from_query(req.uri().query()) {
+        Ok(q) => q,
+        Err(e) => return Ok(http_400(&e)),
+    };
+    let mut todos = get_tasks(&state, UsersTaskTypes::CompletedTodos).await?;
+    replace_tag_id(&mut todos, &state).await;
+    todos.retain(|t| query.matches(t));
+    todos.sort_by_key(|t| std::cmp::Reverse(t.completed_at));
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&todos).unwrap()))
+        .unwrap())
+}
+
+// POST /v1/todos/:id/score/:direction
+// direction is up or down
+async fn score_todo(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    use habitica_aide::ScoreDirection;
+    let path = url_to_pathvec(req.uri());
+    if path[3] != "score" {
+        return Ok(http_404(&format!(
+            "path not recognized: {}",
+            req.uri().path()
+        )));
+    }
+    let direction = match ScoreDirection::from_str(path[4]) {
+        Ok(d) => d,
+        Err(_) => return Ok(http_404(&format!("Unknown direction {}", path[4]))),
+    };
+    let id = path[2];
+    if !habitica_aide::score_task(&state, id, direction).await? {
+        return Ok(http_404(&format!("todo {} not found", id)));
+    }
+    let response = ResultResponse { success: true };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// POST /v1/todos
+// { name: "todo", tags: ["label_name"], ... }
+async fn add_todo(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    use aide_proto::v1::todo::{Todo, TodoTypes};
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let todo: Todo = match serde_json::from_slice(&body) {
+        Ok(t) => t,
+        Err(e) => return Ok(http_400(&format!("Invalid todo: {}", e))),
+    };
+    if todo.name.is_empty() {
+        return Ok(http_400(&"The name of the todo is missing"));
+    }
+    // with the tags classification, dailys are listed only if they have the label of
+    // their type
+    let mut labels = todo.tags.clone();
+    if state.classification == cli::Classification::Tags {
+        match todo.todo_type {
+            TodoTypes::Daily => labels.push("daily".to_string()),
+            TodoTypes::Weekly => labels.push("weekly".to_string()),
+            TodoTypes::Task | TodoTypes::Habit | TodoTypes::Reward => (),
+        }
+    }
+    let tag_ids = match resolve_labels(&state, labels).await {
+        Ok(tag_ids) => tag_ids,
+        Err(unknown) => return Ok(http_400(&format!("Unknown labels: {}", unknown.join(", ")))),
+    };
+    let created = habitica_aide::create_task(&state, &todo, tag_ids).await?;
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&created).unwrap()))
+        .unwrap())
+}
+
+/// Resolve label names to tag IDs, returning the unknown labels as error
+async fn resolve_labels(
+    state: &HabiticaState,
+    labels: Vec<String>,
+) -> Result<Vec<String>, Vec<String>> {
+    let mut tag_ids = Vec::new();
+    let mut unknown_labels = Vec::new();
+    for label in labels {
+        match habitica_aide::get_tag_id(state, &label).await {
+            Some(id) if !tag_ids.contains(&id) => tag_ids.push(id),
+            Some(_) => (),
+            None => unknown_labels.push(label),
+        }
+    }
+    if unknown_labels.is_empty() {
+        Ok(tag_ids)
+    } else {
+        Err(unknown_labels)
+    }
+}
+
+// PUT /v1/todos/:id
+// { name: "new name", tags: ["label_name"] }
+async fn edit_todo(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    use aide_proto::v1::todo::TodoUpdate;
+    let path = url_to_pathvec(req.uri());
+    let id = path[2].to_string();
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let mut update: TodoUpdate = match serde_json::from_slice(&body) {
+        Ok(u) => u,
+        Err(e) => return Ok(http_400(&format!("Invalid todo update: {}", e))),
+    };
+    if update.name.as_deref() == Some("") {
+        return Ok(http_400(&"The name of the todo cannot be empty"));
+    }
+    if update.clear_due_date && update.due_date.is_some() {
+        return Ok(http_400(&"The due date cannot be both set and cleared"));
+    }
+    if let Some(labels) = update.tags.take() {
+        match resolve_labels(&state, labels).await {
+            Ok(tag_ids) => update.tags = Some(tag_ids),
+            Err(unknown) => {
+                return Ok(http_400(&format!("Unknown labels: {}", unknown.join(", "))))
+            }
+        }
+    }
+    match habitica_aide::update_task(&state, &id, &update).await? {
+        Some(todo) => Ok(Response::builder()
+            .body(Body::from(serde_json::to_string(&todo).unwrap()))
+            .unwrap()),
+        None => Ok(http_404(&format!("todo {} not found", id))),
+    }
+}
+
+// DELETE /v1/todos/:id
+async fn delete_todo(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    let path = url_to_pathvec(req.uri());
+    let id = path[2];
+    if !habitica_aide::delete_task(&state, id).await? {
+        return Ok(http_404(&format!("todo {} not found", id)));
+    }
+    let response = ResultResponse { success: true };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// POST /v1/todos/:id/done
+// DELETE /v1/todos/:id/done
+async fn done_todo(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    use habitica_aide::ScoreDirection;
+    let path = url_to_pathvec(req.uri());
+    if path.len() != 4 {
+        return Ok(http_404(&format!("Path has wrong length: {}", path.len())));
+    }
+    let direction = match *req.method() {
+        Method::POST => ScoreDirection::Up,
+        Method::DELETE => ScoreDirection::Down,
+        _ => return Ok(http_404(&"The only methods supported are POST and DELETE")),
+    };
+    let id = path[2];
+    if !habitica_aide::score_task(&state, id, direction).await? {
+        return Ok(http_404(&format!("todo {} not found", id)));
+    }
+    let response = ResultResponse { success: true };
+    Ok(Response::builder()
+        .body(Body::from(serde_json::to_string(&response).unwrap()))
+        .unwrap())
+}
+
+// POST /v1/todos/:id/checklist/:item_id/toggle
+async fn toggle_checklist_item(
+    req: Request<Body>,
+    state: HabiticaState,
+) -> Result<Response<Body>, anyhow::Error> {
+    let path = url_to_pathvec(req.uri());
+    if path.len() != 6 || path[3] != "checklist" {
+        return Ok(http_404(&format!(
+            "path not recognized: {}",
+            req.uri().path()
+        )));
+    }
+    let (id, item_id) = (path[2], path[4]);
+    match habitica_aide::score_checklist_item(&state, id, item_id).await? {
+        Some(item) => Ok(Response::builder()
+            .body(Body::from(serde_json::to_string(&item).unwrap()))
+            .unwrap()),
+        None => Ok(http_404(&format!(
+            "checklist item {} of todo {} not found",
+            item_id, id
+        ))),
+    }
+}
+
 //async fn get_todo(state: &HabiticaState) -> tide::Result {
 //let mut todos = get_tasks_with_type(state, "todos").await?;
 //let dailys = get_tasks_with_type(state, "dailys").await?;
@@ -341,21 +668,3 @@ async fn todos(req: Request<Body>, state: HabiticaState) -> Result<Response<Body
 //Ok(todos)
 //}
 //}
-
-//async fn subscribe_module() -> Result<(), Box<dyn std::error::Error>> {
-//let web_hook = format!("http://0.0.0.0:{}", HABITICA_PORT);
-//let payload = aide_proto::v1::module::Module {
-//name: "habitica-plugin".to_string(),
-//kind: aide_proto::v1::module::ModuleType::Todo,
-//web_hook,
-//};
-//let req = surf::post("http://localhost:9090/v1/modules")
-//.body(surf::Body::from_json(
-//&aide_proto::v1::ModuleRequestType::Subscribe(payload),
-//)?)
-//.await?;
-//if req.status() == surf::StatusCode::Ok {
-//return Ok(());
-//}
-//panic!("Connection failed");
-//}
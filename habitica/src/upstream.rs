@@ -0,0 +1,339 @@
+use hyper::{Body, Response, StatusCode};
+use log::{debug, warn};
+use reqwest::header::HeaderMap;
+use reqwest_pool::{ReqwestPool, ReqwestPoolError};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+const MAX_RETRIES: u32 = 3;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+/// The longest wait for the rate limit before a request is sent; above it, clients are
+/// told to retry later
+const MAX_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum UpstreamError {
+    #[error("Habitica rate limit exceeded, retry in {} seconds", .retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    #[error("Habitica replied {status}: {message}")]
+    Status {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+    #[error("Request to Habitica failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("No HTTP client available: {0}")]
+    Pool(#[from] ReqwestPoolError),
+}
+
+impl UpstreamError {
+    /// Fail if Habitica didn't reply with a success, using the message Habitica sent
+    pub async fn check(response: reqwest::Response) -> Result<reqwest::Response, Self> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after =
+                rate_limit_wait(response.headers(), chrono::Utc::now()).unwrap_or(BASE_BACKOFF);
+            return Err(UpstreamError::RateLimited { retry_after });
+        }
+        let body = response.text().await?;
+        Err(UpstreamError::Status {
+            status,
+            message: error_message(&body),
+        })
+    }
+    /// The response to send to aide clients
+    pub fn to_response(&self) -> Response<Body> {
+        match self {
+            UpstreamError::RateLimited { retry_after } => Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(
+                    hyper::header::RETRY_AFTER,
+                    retry_after.as_secs_f64().ceil() as u64,
+                )
+                .body(Body::from(self.to_string()))
+                .unwrap(),
+            _ => aide_common::http_502(self),
+        }
+    }
+}
+
+/// The message of an Habitica error, like `{"success":false,"message":"..."}`, or the
+/// whole body if it's not an Habitica error
+fn error_message(body: &str) -> String {
+    #[derive(serde::Deserialize)]
+    struct RespError {
+        message: String,
+    }
+    serde_json::from_str::<RespError>(body)
+        .map(|e| e.message)
+        .unwrap_or_else(|_| body.to_string())
+}
+
+/// How long to wait before the next request, according to the rate limit headers of a
+/// response
+fn rate_limit_wait(headers: &HeaderMap, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(seconds) = header("retry-after").and_then(|v| v.trim().parse::<u64>().ok()) {
+        return Some(Duration::from_secs(seconds));
+    }
+    let remaining = header("x-ratelimit-remaining").and_then(|v| v.trim().parse::<u32>().ok());
+    if remaining != Some(0) {
+        return None;
+    }
+    // Habitica sends the reset time as a JavaScript date, like
+    // `Thu Mar 04 2021 10:00:00 GMT+0000 (Coordinated Universal Time)`
+    let reset = header("x-ratelimit-reset")?;
+    let reset = chrono::DateTime::parse_from_str(
+        reset.split(" (").next().unwrap_or_default(),
+        "%a %b %d %Y %H:%M:%S GMT%z",
+    )
+    .or_else(|_| chrono::DateTime::parse_from_rfc2822(reset))
+    .or_else(|_| chrono::DateTime::parse_from_rfc3339(reset))
+    .ok()?;
+    Some(
+        (reset.with_timezone(&chrono::Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// The exponential backoff from `base` before the retry `attempt`, with up to 50% of
+/// jitter
+fn backoff(attempt: u32, base: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+    let delay = base * 2u32.pow(attempt);
+    // a randomly seeded hasher is enough of a random source for the jitter
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    delay + delay.mul_f64((random % 1000) as f64 / 2000.0)
+}
+
+/// The requests to Habitica, shared by all handlers to respect the rate limits
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// When requests can be sent again
+    resume_at: Arc<Mutex<Option<Instant>>>,
+    /// The backoff before the first retry, doubled at each retry
+    base_backoff: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            resume_at: Arc::default(),
+            base_backoff: BASE_BACKOFF,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Send a request with a client of `pool`, waiting for the rate limit
+    ///
+    /// Rate limited requests are retried, as Habitica didn't process them; GETs are
+    /// retried also on server errors and connection failures. The client goes back to
+    /// the pool between the attempts. The response of the last attempt is returned as it
+    /// is
+    pub async fn send(
+        &self,
+        pool: &ReqwestPool,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response, UpstreamError> {
+        let idempotent = request.method() == reqwest::Method::GET;
+        let mut attempt = 0;
+        loop {
+            self.wait().await?;
+            let next = request.try_clone();
+            let result = {
+                let handler = pool.get_handler().await?;
+                handler.get_client().execute(request).await
+            };
+            let wait = match &result {
+                Ok(response) => rate_limit_wait(response.headers(), chrono::Utc::now()),
+                Err(_) => None,
+            };
+            if let Some(wait) = wait {
+                self.pause(wait).await;
+            }
+            let retry_in = match &result {
+                Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    Some(if wait.is_some() {
+                        Duration::ZERO
+                    } else {
+                        backoff(attempt, self.base_backoff)
+                    })
+                }
+                Ok(response) if response.status().is_server_error() && idempotent => {
+                    Some(backoff(attempt, self.base_backoff))
+                }
+                Err(e) if idempotent && (e.is_connect() || e.is_timeout()) => {
+                    Some(backoff(attempt, self.base_backoff))
+                }
+                _ => None,
+            };
+            match (retry_in, next) {
+                (Some(retry_in), Some(next)) if attempt < MAX_RETRIES => {
+                    match &result {
+                        Ok(response) => warn!(
+                            "Habitica replied {} to {}, retrying",
+                            response.status(),
+                            next.url().path()
+                        ),
+                        Err(e) => warn!("Request to Habitica failed [{}], retrying", e),
+                    }
+                    tokio::time::sleep(retry_in).await;
+                    request = next;
+                    attempt += 1;
+                }
+                _ => return Ok(result?),
+            }
+        }
+    }
+    async fn wait(&self) -> Result<(), UpstreamError> {
+        let resume_at = *self.resume_at.lock().await;
+        if let Some(resume_at) = resume_at {
+            let wait = resume_at.saturating_duration_since(Instant::now());
+            if wait > MAX_WAIT {
+                return Err(UpstreamError::RateLimited { retry_after: wait });
+            }
+            if !wait.is_zero() {
+                debug!("Waiting {:?} for the Habitica rate limit", wait);
+                tokio::time::sleep(wait).await;
+            }
+        }
+        Ok(())
+    }
+    async fn pause(&self, wait: Duration) {
+        let mut resume_at = self.resume_at.lock().await;
+        let at = Instant::now() + wait;
+        *resume_at = Some(resume_at.map_or(at, |current| current.max(at)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (k, v) in values {
+            headers.insert(*k, v.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_rate_limit_wait() {
+        let now = chrono::Utc.with_ymd_and_hms(2021, 3, 4, 9, 59, 30).unwrap();
+        assert_eq!(rate_limit_wait(&HeaderMap::new(), now), None);
+        let uut = headers(&[("Retry-After", "5")]);
+        assert_eq!(rate_limit_wait(&uut, now), Some(Duration::from_secs(5)));
+        let reset = "Thu Mar 04 2021 10:00:00 GMT+0000 (Coordinated Universal Time)";
+        let uut = headers(&[("X-RateLimit-Remaining", "3"), ("X-RateLimit-Reset", reset)]);
+        assert_eq!(rate_limit_wait(&uut, now), None);
+        let uut = headers(&[("X-RateLimit-Remaining", "0"), ("X-RateLimit-Reset", reset)]);
+        assert_eq!(rate_limit_wait(&uut, now), Some(Duration::from_secs(30)));
+        // a reset in the past
+        let now = chrono::Utc.with_ymd_and_hms(2021, 3, 4, 10, 0, 30).unwrap();
+        assert_eq!(rate_limit_wait(&uut, now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_backoff() {
+        for attempt in 0..MAX_RETRIES {
+            let delay = BASE_BACKOFF * 2u32.pow(attempt);
+            let uut = backoff(attempt, BASE_BACKOFF);
+            assert!(uut >= delay);
+            assert!(uut <= delay + delay / 2);
+        }
+    }
+
+    #[test]
+    fn test_error_message() {
+        let body = r#"{"success":false,"error":"NotFound","message":"Task not found."}"#;
+        assert_eq!(error_message(body), "Task not found.");
+        assert_eq!(error_message("Bad Gateway"), "Bad Gateway");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let uut = UpstreamError::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        }
+        .to_response();
+        assert_eq!(uut.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(uut.headers()[hyper::header::RETRY_AFTER], "2");
+        let uut = UpstreamError::Status {
+            status: reqwest::StatusCode::UNAUTHORIZED,
+            message: "Missing authentication headers.".to_string(),
+        }
+        .to_response();
+        assert_eq!(uut.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    /// A server replying with `statuses`, in order, then with 200; it returns its URL
+    /// and the number of requests it served
+    async fn fake_habitica(statuses: Vec<u16>) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use hyper::service::{make_service_fn, service_fn};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let service = make_service_fn(move |_| {
+            let statuses = statuses.clone();
+            let counter = counter.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |_req| {
+                    let hit = counter.fetch_add(1, Ordering::SeqCst);
+                    let status = statuses.get(hit).copied().unwrap_or(200);
+                    async move {
+                        Response::builder()
+                            .status(status)
+                            .header("Retry-After", "0")
+                            .body(Body::from(r#"{"success":true}"#))
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+        (url, hits)
+    }
+
+    #[tokio::test]
+    async fn test_send_retries() {
+        use std::sync::atomic::Ordering;
+        let client = reqwest::Client::new();
+        let pool = reqwest_pool::ReqwestPoolBuilder::new(1)
+            .unwrap()
+            .build()
+            .await;
+        let uut = RateLimit {
+            base_backoff: Duration::from_millis(1),
+            ..RateLimit::default()
+        };
+        let send = |request: reqwest::RequestBuilder| uut.send(&pool, request.build().unwrap());
+        let (url, hits) = fake_habitica(vec![429, 503]).await;
+        let response = send(client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        // only rate limited POSTs are retried
+        let (url, hits) = fake_habitica(vec![429, 503]).await;
+        let response = send(client.post(&url)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        let (url, hits) = fake_habitica(vec![500; 10]).await;
+        let response = send(client.get(&url)).await.unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), MAX_RETRIES as usize + 1);
+    }
+}
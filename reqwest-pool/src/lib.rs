@@ -115,7 +115,7 @@ impl<'a> Handler<'a> {
     /// # })
     /// ```
     pub fn get_client(&'a self) -> &'a Client {
-        &*self.mg
+        &self.mg
     }
 }
 #[cfg(test)]
@@ -8,9 +8,15 @@ pub struct Opt {
     #[clap(short, long, arg_enum)]
     /// Optional parameter to get only one type of todos
     pub todo_type: Option<TodoTypes>,
-    /// Specify labels
+    /// Specify labels, separated by commas
     #[clap(short, long)]
     pub label: Option<String>,
+    /// Show only the todos with all the labels, instead of any of them
+    #[clap(long)]
+    pub all_labels: bool,
+    /// Show only the todos due in the given period
+    #[clap(long, arg_enum)]
+    pub due: Option<DueFilter>,
     #[clap(short = 'T', long)]
     pub tui: bool,
     #[clap(flatten)]
@@ -26,6 +32,18 @@ pub enum TodoTypes {
     Weekly,
     Task,
     Periodic,
+    Habit,
+    Reward,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum)]
+pub enum DueFilter {
+    /// Due today or overdue
+    Today,
+    /// Due by the end of the week (sunday) or overdue
+    Week,
+    /// Not done and due before today
+    Overdue,
 }
 
 #[derive(Debug, Clone, Subcommand)]
@@ -41,4 +59,106 @@ pub enum Subcommands {
         #[clap(short, long)]
         delete: bool,
     },
+    /// Add a new todo
+    Add {
+        name: String,
+        /// A longer description of the todo
+        #[clap(short, long)]
+        descr: Option<String>,
+        /// The type of the todo (task, daily, weekly, habit or reward)
+        #[clap(short, long, default_value = "task")]
+        todo_type: aide_proto::v1::todo::TodoTypes,
+        /// Add a label, it can be repeated
+        #[clap(short, long)]
+        label: Vec<String>,
+        /// Add an item to the checklist, it can be repeated
+        #[clap(short, long)]
+        checklist: Vec<String>,
+        /// The due date, as YYYY-MM-DD or as RFC 3339 date and time
+        #[clap(long)]
+        due: Option<aide_proto::v1::todo::DueDate>,
+        /// The module to add the todo to, when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Edit a todo, changing only the given fields
+    Edit {
+        id: String,
+        /// The new name of the todo
+        #[clap(short, long)]
+        name: Option<String>,
+        /// The new description of the todo
+        #[clap(short, long)]
+        descr: Option<String>,
+        /// Replace the labels, it can be repeated
+        #[clap(short, long)]
+        label: Vec<String>,
+        /// Remove all the labels
+        #[clap(long, conflicts_with = "label")]
+        clear_labels: bool,
+        /// Replace the checklist, it can be repeated; the existing items with the same
+        /// name are kept as they are
+        #[clap(short, long)]
+        checklist: Vec<String>,
+        /// The new due date, as YYYY-MM-DD or as RFC 3339 date and time
+        #[clap(long)]
+        due: Option<aide_proto::v1::todo::DueDate>,
+        /// Remove the due date
+        #[clap(long, conflicts_with = "due")]
+        no_due: bool,
+        /// The module providing the todo, needed when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Delete a todo
+    Rm {
+        id: String,
+        /// The module providing the todo, needed when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Mark a todo as done
+    Done {
+        id: String,
+        /// The module providing the todo, needed when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Check or uncheck an item of the checklist of a todo
+    Check {
+        id: String,
+        item_id: String,
+        /// The module providing the todo, needed when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Score a habit up, or down, or buy a reward
+    Score {
+        id: String,
+        /// Score the habit down instead of up
+        #[clap(long)]
+        down: bool,
+        /// The module providing the todo, needed when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Show the completed todos, grouped by day
+    Log {
+        /// Show the todos completed since this day [default: a week ago]
+        #[clap(long)]
+        since: Option<aide_proto::v1::todo::DueDate>,
+        /// Specify labels, separated by commas
+        #[clap(short, long)]
+        label: Option<String>,
+        /// The module to ask, instead of all of them, when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
+    /// Mark a todo as not done
+    Undo {
+        id: String,
+        /// The module providing the todo, needed when talking to the aide hub
+        #[clap(short, long)]
+        source: Option<String>,
+    },
 }
@@ -1,7 +1,8 @@
 mod cli;
-use aide_proto::v1::{todo::TodoTypes, ResultResponse, Todo as AideTodo};
+use aide_proto::v1::{todo::TodoTypes, ResultResponse, Todo as AideTodo, TodoListResponse};
 use clap::Parser;
 use crossterm::event::{Event, KeyCode};
+use serde::Deserialize;
 
 fn main() -> Result<(), anyhow::Error> {
     let opt: cli::Opt = cli::Opt::parse();
@@ -11,97 +12,352 @@ fn main() -> Result<(), anyhow::Error> {
         opt.common_opt.host_addr,
         opt.common_opt.port
     ))?;
-    if opt.command.is_some() {
-        if let Some(cli::Subcommands::Label {
+    let client = opt.common_opt.get_client()?;
+    if let Some(command) = &opt.command {
+        return run_command(command, &client, &base_url);
+    }
+    let mut url = base_url.join("todos")?;
+    let query = todo_query(&opt).to_query();
+    if !query.is_empty() {
+        url.set_query(Some(&query));
+    }
+    let todos = get_todo_list(&client, url)?;
+    let mut temp_todos: Vec<&AideTodo> = todos.iter().collect();
+    // todos without a due date go last
+    temp_todos.sort_by_key(|t| (t.due_date.is_none(), t.due_date.map(|d| d.date())));
+
+    if opt.tui {
+        tui_todo(&temp_todos)?;
+    } else {
+        temp_todos.iter().for_each(print_todo);
+        println!("total: {}", get_todos_count(&temp_todos));
+    }
+
+    Ok(())
+}
+
+/// Translate the options in the filters applied by the server
+fn todo_query(opt: &cli::Opt) -> aide_proto::v1::TodoQuery {
+    use aide_proto::v1::todo::{DueDate, LabelMode};
+    use chrono::Datelike;
+    let types = match opt.todo_type {
+        Some(cli::TodoTypes::Task) => vec![TodoTypes::Task],
+        Some(cli::TodoTypes::Daily) => vec![TodoTypes::Daily],
+        Some(cli::TodoTypes::Weekly) => vec![TodoTypes::Weekly],
+        Some(cli::TodoTypes::Periodic) => vec![TodoTypes::Daily, TodoTypes::Weekly],
+        Some(cli::TodoTypes::Habit) => vec![TodoTypes::Habit],
+        Some(cli::TodoTypes::Reward) => vec![TodoTypes::Reward],
+        None => Vec::new(),
+    };
+    let mut query = aide_proto::v1::TodoQuery {
+        types,
+        ..Default::default()
+    };
+    if let Some(label) = &opt.label {
+        query.labels = label.split(',').map(|l| l.to_string()).collect();
+    }
+    if opt.all_labels {
+        query.label_mode = LabelMode::All;
+    }
+    let today = chrono::Local::now().date_naive();
+    match opt.due {
+        Some(cli::DueFilter::Today) => {
+            query.due_before = today.succ_opt().map(DueDate::from);
+        }
+        Some(cli::DueFilter::Week) => {
+            let days_to_monday = 7 - today.weekday().num_days_from_monday() as i64;
+            query.due_before = Some(DueDate::from(
+                today + chrono::Duration::days(days_to_monday),
+            ));
+        }
+        Some(cli::DueFilter::Overdue) => {
+            query.due_before = Some(DueDate::from(today));
+            query.done = Some(false);
+        }
+        None => (),
+    }
+    query
+}
+
+fn run_command(
+    command: &cli::Subcommands,
+    client: &reqwest::blocking::Client,
+    base_url: &reqwest::Url,
+) -> Result<(), anyhow::Error> {
+    match command {
+        cli::Subcommands::Label {
             name,
             create,
             delete,
-        }) = &opt.command
-        {
+        } => {
             if *create {
                 use aide_proto::v1::todo::Label;
                 let label = Label { name: name.clone() };
                 let url = base_url.join("labels")?;
-                let client = reqwest::blocking::Client::new();
                 let _res: ResultResponse = client
                     .post(url)
                     .body(serde_json::to_string(&label)?)
                     .send()?
+                    .error_for_status()?
                     .json()?;
-                return Ok(());
             } else {
                 assert!(delete);
                 let url_path = format!("labels/{name}");
                 let url = base_url.join(&url_path)?;
-                let client = reqwest::blocking::Client::new();
-                let _res: ResultResponse = client.delete(url).send()?.json()?;
-                return Ok(());
+                let _res: ResultResponse = client.delete(url).send()?.error_for_status()?.json()?;
             }
         }
-    }
-    let todos: Vec<AideTodo> = match opt.todo_type {
-        Some(cli::TodoTypes::Task) => {
-            let url = base_url.join("types/task/todos")?;
-            let res = reqwest::blocking::get(url)?;
-            res.json()?
-        }
-        Some(cli::TodoTypes::Daily) => {
-            let url = base_url.join("types/daily/todos")?;
-            let res = reqwest::blocking::get(url)?;
-            res.json()?
-        }
-        Some(cli::TodoTypes::Weekly) => {
-            let url = base_url.join("types/weekly/todos")?;
-            let res = reqwest::blocking::get(url)?;
-            res.json()?
-        }
-        Some(cli::TodoTypes::Periodic) => {
-            let url = base_url.join("types/daily/todos")?;
-            let res = reqwest::blocking::get(url)?;
-            let mut todos: Vec<AideTodo> = res.json()?;
-            let url = base_url.join("types/weekly/todos")?;
-            let res = reqwest::blocking::get(url)?;
-            let mut temp_todos: Vec<AideTodo> = res.json()?;
-            todos.append(&mut temp_todos);
-            todos
-        }
-        None => {
-            let url = base_url.join("todos")?;
-            let res = reqwest::blocking::get(url)?;
-            res.json()?
+        cli::Subcommands::Add {
+            name,
+            descr,
+            todo_type,
+            label,
+            checklist,
+            due,
+            source,
+        } => {
+            use aide_proto::v1::todo::CheckListItem;
+            let todo = AideTodo {
+                name: name.clone(),
+                descr: descr.clone(),
+                tags: label.clone(),
+                todo_type: *todo_type,
+                checklist: checklist
+                    .iter()
+                    .map(|c| CheckListItem {
+                        id: None,
+                        name: c.clone(),
+                        done: false,
+                    })
+                    .collect(),
+                due_date: *due,
+                ..AideTodo::default()
+            };
+            let url = todo_url(base_url, "todos", source)?;
+            let created: AideTodo = client
+                .post(url)
+                .body(serde_json::to_string(&todo)?)
+                .send()?
+                .error_for_status()?
+                .json()?;
+            print_todo(&&created);
         }
-    };
-    let temp_todos: Vec<&AideTodo> = if let Some(label) = opt.label {
-        todos.iter().filter(|t| t.tags.contains(&label)).collect()
-    } else {
-        todos.iter().collect()
-    };
+        cli::Subcommands::Edit {
+            id,
+            name,
+            descr,
+            label,
+            clear_labels,
+            checklist,
+            due,
+            no_due,
+            source,
+        } => {
+            use aide_proto::v1::todo::{CheckListItem, TodoUpdate};
+            let update = TodoUpdate {
+                name: name.clone(),
+                descr: descr.clone(),
+                tags: (!label.is_empty() || *clear_labels).then(|| label.clone()),
+                checklist: (!checklist.is_empty()).then(|| {
+                    checklist
+                        .iter()
+                        .map(|c| CheckListItem {
+                            id: None,
+                            name: c.clone(),
+                            done: false,
+                        })
+                        .collect()
+                }),
+                due_date: *due,
+                clear_due_date: *no_due,
+            };
+            let url = todo_url(base_url, &format!("todos/{id}"), source)?;
+            let updated: AideTodo = client
+                .put(url)
+                .body(serde_json::to_string(&update)?)
+                .send()?
+                .error_for_status()?
+                .json()?;
+            print_todo(&&updated);
+        }
+        cli::Subcommands::Rm { id, source } => {
+            let url = todo_url(base_url, &format!("todos/{id}"), source)?;
+            let _res: ResultResponse = client.delete(url).send()?.error_for_status()?.json()?;
+        }
+        cli::Subcommands::Done { id, source } => {
+            let url = todo_url(base_url, &format!("todos/{id}/done"), source)?;
+            let _res: ResultResponse = client.post(url).send()?.error_for_status()?.json()?;
+        }
+        cli::Subcommands::Check {
+            id,
+            item_id,
+            source,
+        } => {
+            use aide_proto::v1::todo::CheckListItem;
+            let url_path = format!("todos/{id}/checklist/{item_id}/toggle");
+            let url = todo_url(base_url, &url_path, source)?;
+            let item: CheckListItem = client.post(url).send()?.error_for_status()?.json()?;
+            println!("{}", item);
+        }
+        cli::Subcommands::Score { id, down, source } => {
+            let direction = if *down { "down" } else { "up" };
+            let url = todo_url(base_url, &format!("todos/{id}/score/{direction}"), source)?;
+            let _res: ResultResponse = client.post(url).send()?.error_for_status()?.json()?;
+        }
+        cli::Subcommands::Log {
+            since,
+            label,
+            source,
+        } => {
+            use aide_proto::v1::todo::DueDate;
+            let week_ago = chrono::Local::now().date_naive() - chrono::Duration::days(7);
+            let query = aide_proto::v1::TodoQuery {
+                since: Some(since.unwrap_or(DueDate::from(week_ago))),
+                labels: label
+                    .iter()
+                    .flat_map(|l| l.split(','))
+                    .map(|l| l.to_string())
+                    .collect(),
+                ..Default::default()
+            };
+            let mut url = base_url.join("todos/completed")?;
+            url.set_query(Some(&query.to_query()));
+            if let Some(source) = source {
+                url.query_pairs_mut().append_pair("source", source);
+            }
+            let todos = get_todo_list(client, url)?;
+            print_log(&todos);
+        }
+        cli::Subcommands::Undo { id, source } => {
+            let url = todo_url(base_url, &format!("todos/{id}/done"), source)?;
+            let _res: ResultResponse = client.delete(url).send()?.error_for_status()?.json()?;
+        }
+    }
+    Ok(())
+}
 
-    if opt.tui {
-        tui_todo(&temp_todos)?;
+/// Build the URL of a request about a specific todo, selecting the module that provides it
+fn todo_url(
+    base_url: &reqwest::Url,
+    path: &str,
+    source: &Option<String>,
+) -> Result<reqwest::Url, anyhow::Error> {
+    let mut url = base_url.join(path)?;
+    if let Some(source) = source {
+        url.query_pairs_mut().append_pair("source", source);
+    }
+    Ok(url)
+}
+
+/// `GET /v1/todos` replies with a plain list when served by a plugin, while the aide hub
+/// merges the todos of all plugins in a [`TodoListResponse`]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TodoListReply {
+    Plain(Vec<AideTodo>),
+    Aggregated(TodoListResponse),
+}
+
+/// Get a list of todos, printing the modules that failed when talking to the aide hub
+///
+/// The hub replies 502 when all the modules failed, still listing their errors
+fn get_todo_list(
+    client: &reqwest::blocking::Client,
+    url: reqwest::Url,
+) -> Result<Vec<AideTodo>, anyhow::Error> {
+    let res = client.get(url).send()?;
+    let status = res.status();
+    let res = if status == reqwest::StatusCode::BAD_GATEWAY {
+        res
     } else {
-        temp_todos.iter().for_each(print_todo);
-        println!("total: {}", get_todos_count(&temp_todos));
+        res.error_for_status()?
+    };
+    let body = res.text()?;
+    let todos = match serde_json::from_str(&body) {
+        Ok(TodoListReply::Plain(todos)) => todos,
+        Ok(TodoListReply::Aggregated(response)) => {
+            response
+                .errors
+                .iter()
+                .for_each(|e| eprintln!("Failed to get todos from {}: {}", e.module, e.error));
+            response.data
+        }
+        Err(e) if status.is_success() => return Err(e.into()),
+        Err(_) => anyhow::bail!("{}: {}", status, body),
+    };
+    if !status.is_success() {
+        anyhow::bail!("No todo module replied ({})", status);
     }
+    Ok(todos)
+}
 
-    Ok(())
+/// Print the completed todos grouped by day, the most recent first
+fn print_log(todos: &[AideTodo]) {
+    let mut todos: Vec<&AideTodo> = todos.iter().collect();
+    todos.sort_by_key(|t| std::cmp::Reverse(t.completed_at));
+    let mut current_day = None;
+    for t in todos {
+        let day = t
+            .completed_at
+            .map(|c| c.with_timezone(&chrono::Local).date_naive());
+        if day != current_day {
+            match day {
+                Some(d) => println!("{}", d.format("%Y-%m-%d %A")),
+                None => println!("unknown day"),
+            }
+            current_day = day;
+        }
+        println!("  {} {}", type_symbol(t.todo_type), t.name);
+    }
 }
 
-fn print_todo(t: &&AideTodo) {
-    let type_symbol = match t.todo_type {
+fn type_symbol(todo_type: TodoTypes) -> &'static str {
+    match todo_type {
         TodoTypes::Task => "[T]",
         TodoTypes::Daily => "[D]",
         TodoTypes::Weekly => "[W]",
-    };
+        TodoTypes::Habit => "[H]",
+        TodoTypes::Reward => "[R]",
+    }
+}
+
+fn print_todo(t: &&AideTodo) {
+    let type_symbol = type_symbol(t.todo_type);
     println!("{} {}", type_symbol, t.name);
     if !t.checklist.is_empty() {
         t.checklist
             .iter()
             .filter(|c| !c.done)
-            .for_each(|c| println!("\t{}", c.name));
+            .for_each(|c| match &c.id {
+                Some(id) => println!("\t{} [{}]", c.name, id),
+                None => println!("\t{}", c.name),
+            });
     }
-    if t.due_date.is_some() {
-        println!("\tdue date: {}", t.due_date.to_owned().unwrap())
+    if let Some(due_date) = t.due_date {
+        if t.is_overdue(chrono::Local::now().date_naive()) {
+            use crossterm::style::Stylize;
+            use std::io::IsTerminal;
+            let line = format!("due date: {} (overdue)", due_date);
+            if std::io::stdout().is_terminal() {
+                println!("\t{}", line.red())
+            } else {
+                println!("\t{}", line)
+            }
+        } else {
+            println!("\tdue date: {}", due_date)
+        }
+    }
+    if let Some(habit) = &t.habit {
+        println!("\tscore: +{} -{}", habit.counter_up, habit.counter_down)
+    }
+    if let Some(cost) = t.cost {
+        println!("\tcost: {}", cost)
+    }
+    if let Some(id) = &t.id {
+        match &t.source {
+            Some(source) => println!("\tid: {} (source: {})", id, source),
+            None => println!("\tid: {}", id),
+        }
     }
 }
 
@@ -146,6 +402,13 @@ impl<'a> aide_common::tui::ToStringVec for TodoStatefulList<'a> {
             })
             .collect()
     }
+    fn to_color_vec(&self) -> Vec<Option<tui::style::Color>> {
+        let today = chrono::Local::now().date_naive();
+        self.todo_list
+            .iter()
+            .map(|t| t.is_overdue(today).then_some(tui::style::Color::Red))
+            .collect()
+    }
 }
 impl<'a> aide_common::tui::ToListState for TodoStatefulList<'a> {
     fn to_state(&self) -> tui::widgets::ListState {
@@ -204,11 +467,7 @@ fn tui_todo_internal(
 }
 
 fn todo_to_one_line(t: &&AideTodo) -> String {
-    let type_symbol = match t.todo_type {
-        TodoTypes::Task => "[T]",
-        TodoTypes::Daily => "[D]",
-        TodoTypes::Weekly => "[W]",
-    };
+    let type_symbol = type_symbol(t.todo_type);
     format!("{} {}", type_symbol, t.name)
 }
 
@@ -222,12 +481,29 @@ fn todo_to_multi_line(t: &&AideTodo) -> String {
             .filter(|c| !c.done)
             .for_each(|c| result.push_str(&format!("{INDENTATION}[] {}\n", c.name)));
     }
-    if t.due_date.is_some() {
+    if let Some(due_date) = t.due_date {
+        result.push_str(&format!("{INDENTATION}due date: {due_date}"));
+        if t.is_overdue(chrono::Local::now().date_naive()) {
+            result.push_str(" (overdue)");
+        }
+        result.push('\n');
+    }
+    if let Some(habit) = &t.habit {
         result.push_str(&format!(
-            "{INDENTATION}due date: {}\n",
-            t.due_date.to_owned().unwrap()
+            "{INDENTATION}score: +{} -{}\n",
+            habit.counter_up, habit.counter_down
         ));
     }
+    if let Some(cost) = t.cost {
+        result.push_str(&format!("{INDENTATION}cost: {cost}\n"));
+    }
+    if let Some(id) = &t.id {
+        result.push_str(&format!("{INDENTATION}id: {id}"));
+        if let Some(source) = &t.source {
+            result.push_str(&format!(" (source: {source})"));
+        }
+        result.push('\n');
+    }
     if !t.tags.is_empty() {
         result.push_str(&format!("{INDENTATION}labels: "));
         t.tags
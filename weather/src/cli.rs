@@ -15,17 +15,12 @@ pub struct Opt {
     pub common_opt: aide_common::CliCommonOpt,
 }
 
-#[derive(Debug, Clone, PartialEq, EnumString, clap::ArgEnum)]
+#[derive(Debug, Clone, Default, PartialEq, EnumString, clap::ArgEnum)]
 #[strum(ascii_case_insensitive)]
 pub enum ForecastTypes {
+    #[default]
     Current,
     Forecast,
     Rain,
     All,
 }
-
-impl Default for ForecastTypes {
-    fn default() -> Self {
-        ForecastTypes::Current
-    }
-}
@@ -28,11 +28,12 @@ fn get_base_url(opt: &cli::Opt) -> Result<reqwest::Url, anyhow::Error> {
 
 fn current(opt: cli::Opt) -> Result<(), anyhow::Error> {
     let base_url = get_base_url(&opt)?;
+    let client = opt.common_opt.get_client()?;
     let url = match opt.location {
         Some(q) => base_url.join("current/")?.join(&q)?,
         None => base_url.join("current")?,
     };
-    let res = reqwest::blocking::get(url)?;
+    let res = client.get(url).send()?.error_for_status()?;
     let cw: aide_proto::v1::weather::CurrentWeather = res.json()?;
     println!("{}", cw.location);
     println!("{}", cw.description);
@@ -45,11 +46,12 @@ fn current(opt: cli::Opt) -> Result<(), anyhow::Error> {
 
 fn forecast(opt: cli::Opt) -> Result<(), anyhow::Error> {
     let base_url = get_base_url(&opt)?;
+    let client = opt.common_opt.get_client()?;
     let url = match opt.location {
         Some(q) => base_url.join("forecast/")?.join(&q)?,
         None => base_url.join("forecast")?,
     };
-    let res = reqwest::blocking::get(url)?;
+    let res = client.get(url).send()?.error_for_status()?;
     let cf: aide_proto::v1::weather::Forecast = res.json()?;
     println!("{}", cf.location);
     println!("{}\t{}", cf.time, cf.description);
@@ -62,11 +64,12 @@ fn forecast(opt: cli::Opt) -> Result<(), anyhow::Error> {
 
 fn rain(opt: cli::Opt) -> Result<(), anyhow::Error> {
     let base_url = get_base_url(&opt)?;
+    let client = opt.common_opt.get_client()?;
     let url = match opt.location {
         Some(q) => base_url.join("hourrainforecast/")?.join(&q)?,
         None => base_url.join("hourrainforecast")?,
     };
-    let res = reqwest::blocking::get(url)?;
+    let res = client.get(url).send()?.error_for_status()?;
     let rf: aide_proto::v1::weather::RainForecast = res.json()?;
     println!("{}", rf.location);
 
@@ -1,11 +1,13 @@
 mod cli;
 mod weatherapi;
 
-use aide_common::{healthz, http_404};
+use aide_common::{check_token, healthz, http_404, shutdown_signal, Registration};
+use aide_proto::v1::ModuleKind;
 use clap::Parser;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server};
 use reqwest_pool::ReqwestPool;
+use tracing::warn;
 
 #[derive(Clone, Debug)]
 struct State {
@@ -18,9 +20,15 @@ async fn main() -> Result<(), anyhow::Error> {
     env_logger::try_init()
         .unwrap_or_else(|e| eprintln!("Failed to initialize env_logger [{:?}]", e));
     let opt = cli::Opt::parse();
-    if opt.common_opt.registration {
-        todo!("Registration not implemented yet!")
-    }
+    let registration = if opt.common_opt.registration {
+        Some(Registration::new(
+            &opt.common_opt,
+            "weatherapi",
+            ModuleKind::Weather,
+        )?)
+    } else {
+        None
+    };
 
     let builder = reqwest_pool::ReqwestPoolBuilder::new(1).unwrap();
     let pool = builder.build().await;
@@ -38,8 +46,24 @@ async fn main() -> Result<(), anyhow::Error> {
     });
 
     let socket_addr = std::net::SocketAddr::new(opt.common_opt.host_addr, opt.common_opt.port);
-    let server = Server::bind(&socket_addr).serve(service);
+    let server = Server::bind(&socket_addr)
+        .serve(service)
+        .with_graceful_shutdown(shutdown_signal());
+    let registration_task = registration.clone().map(|r| {
+        tokio::spawn(async move {
+            r.run().await;
+        })
+    });
     server.await?;
+
+    if let Some(task) = registration_task {
+        task.abort();
+    }
+    if let Some(r) = registration {
+        if let Err(e) = r.deregister().await {
+            warn!("Failed to deregister from aide [{}]", e);
+        }
+    }
     Ok(())
 }
 
@@ -50,6 +74,9 @@ async fn weatherapi_svc(req: Request<Body>, state: State) -> Result<Response<Bod
     if req.uri().path() == "/healthz" {
         return Ok(healthz());
     }
+    if let Some(response) = check_token(&req, state.opt.common_opt.token.as_deref()) {
+        return Ok(response);
+    }
     if !req.uri().path().starts_with("/v1") {
         return Ok(http_404(&"Invalid path"));
     }